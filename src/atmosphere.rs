@@ -0,0 +1,178 @@
+use rand::Rng;
+use std::sync::Arc;
+
+use crate::{
+    geom::{Color, Ray, Vec3},
+    hittable::HitRecord,
+    material::Material,
+};
+
+/// Below this vertical speed, rays are treated as level when integrating
+/// density with height falloff.
+const LEVEL: f64 = 1e-9;
+
+/// A medium that fills the whole world, such as haze or fog, which gives
+/// outdoor scenes aerial perspective without needing a bounding object.
+///
+/// Its density can optionally fall off exponentially with height, so that it
+/// is thickest near the ground.
+pub struct Atmosphere {
+    /// How much light is absorbed per unit of distance, at full density.
+    pub absorption: Color,
+    /// How much light is scattered per unit of distance, at full density.
+    pub scattering: Color,
+    pub falloff: Option<Falloff>,
+    pub phase_function: Arc<dyn Material>,
+}
+
+/// Exponential falloff of density with height.
+#[derive(Clone, Copy, Debug)]
+pub struct Falloff {
+    /// Where the atmosphere is at full density.
+    pub height: f64,
+    /// How much higher it needs to be for the density to drop by a factor of
+    /// `e`.
+    pub scale_height: f64,
+}
+
+/// What happens to a ray on its way through the atmosphere.
+pub struct Passage {
+    /// How the light carried along the ray is scaled.
+    pub weight: Color,
+    /// Where the ray scatters, if it does so before reaching the end of its
+    /// path.
+    pub scatter: Option<HitRecord>,
+}
+
+impl Atmosphere {
+    fn extinction(&self) -> Color {
+        self.absorption + self.scattering
+    }
+
+    /// The density of the atmosphere at the ray's origin, and how quickly it
+    /// changes per unit of `t`, as an exponent.
+    fn density_at_origin(&self, ray: Ray) -> (f64, f64) {
+        match self.falloff {
+            None => (1.0, 0.0),
+            Some(Falloff {
+                height,
+                scale_height,
+            }) => (
+                (-(ray.origin.y() - height) / scale_height).exp(),
+                ray.direction.y() / scale_height,
+            ),
+        }
+    }
+
+    /// How much atmosphere there is along the ray between `t = 0` and `t`, as
+    /// the integral of the density over distance.
+    fn column(&self, ray: Ray, t: f64) -> f64 {
+        let (density, rate) = self.density_at_origin(ray);
+        let length = ray.direction.length() * density;
+        if rate.abs() < LEVEL {
+            length * t
+        } else {
+            length * -(-rate * t).exp_m1() / rate
+        }
+    }
+
+    /// Finds the `t` at which the ray has passed through `column` worth of
+    /// atmosphere, if it ever does.
+    fn distance_through(&self, ray: Ray, column: f64) -> Option<f64> {
+        let (density, rate) = self.density_at_origin(ray);
+        let length = ray.direction.length() * density;
+        if rate.abs() < LEVEL {
+            Some(column / length)
+        } else {
+            let x = column * rate / length;
+            (x < 1.0).then(|| -(-x).ln_1p() / rate)
+        }
+    }
+
+    /// Follows a ray through the atmosphere up to `t_max`, which is where it
+    /// hits a surface, or infinity if it doesn't.
+    ///
+    /// The scattering distance is sampled analytically, using the average of
+    /// the color channels' extinctions. The weight corrects for each channel's
+    /// actual extinction, keeping the result unbiased for colored media.
+    pub fn pass(&self, ray: Ray, t_min: f64, t_max: f64) -> Passage {
+        let extinction = self.extinction();
+        let average = (extinction.r() + extinction.g() + extinction.b()) / 3.0;
+        if average <= 0.0 {
+            return Passage {
+                weight: Color::white(),
+                scatter: None,
+            };
+        }
+
+        let mut rng = rand::thread_rng();
+        let offset = self.column(ray, t_min);
+        let sampled = offset - (1.0 - rng.gen::<f64>()).ln() / average;
+        let t = self.distance_through(ray, sampled).filter(|t| *t < t_max);
+
+        // exp(-(extinction - average) * column), per channel
+        let correction = |column: f64| {
+            let e = (Color::new(average, average, average) - extinction) * (column - offset);
+            Color::new(e.r().exp(), e.g().exp(), e.b().exp())
+        };
+
+        match t {
+            Some(t) => Passage {
+                weight: correction(sampled) * self.scattering / average,
+                scatter: Some(HitRecord::new(
+                    ray.along(t),
+                    t,
+                    ray,
+                    Vec3::new(1.0, 0.0, 0.0),
+                    self.phase_function.clone(),
+                    0.0,
+                    0.0,
+                )),
+            },
+            // rays that escape to infinity have only crossed a finite amount
+            // of atmosphere if they are climbing out of it
+            None => Passage {
+                weight: correction(self.column(ray, t_max)),
+                scatter: None,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        geom::Point3,
+        texture::{Isotropic, SolidColor},
+    };
+
+    #[test]
+    fn test_column_inverse() {
+        let atmosphere = Atmosphere {
+            absorption: Color::new(0.1, 0.1, 0.1),
+            scattering: Color::new(0.2, 0.3, 0.4),
+            falloff: Some(Falloff {
+                height: 0.0,
+                scale_height: 2.0,
+            }),
+            phase_function: Arc::new(Isotropic {
+                albedo: Box::new(SolidColor(Color::white())),
+            }),
+        };
+        let up = Ray::new(Point3::new(0.0, 1.0, 0.0), Vec3::new(1.0, 0.5, 0.0), 0.0);
+        let down = Ray::new(Point3::new(0.0, 1.0, 0.0), Vec3::new(1.0, -0.5, 0.0), 0.0);
+        for ray in [up, down] {
+            for t in [0.0, 0.5, 3.0, 10.0] {
+                let column = atmosphere.column(ray, t);
+                let back = atmosphere.distance_through(ray, column).unwrap();
+                assert!((back - t).abs() < 1e-9, "{t} came back as {back}");
+            }
+        }
+
+        // climbing out of the atmosphere only passes through a limited amount
+        let total = atmosphere.column(up, f64::INFINITY);
+        assert!(total.is_finite());
+        assert!(atmosphere.distance_through(up, total * 1.01).is_none());
+    }
+}
@@ -1,11 +1,15 @@
-use crate::{camera::Camera, geom::Color, hittable::Hittable};
+use crate::{atmosphere::Atmosphere, camera::Camera, geom::Color, hittable::Hittable};
 use serde::{Deserialize, Serialize};
+use std::time::Duration;
 
 pub struct Scene<H: Hittable> {
     pub world: H,
     pub camera: Camera,
     pub image: Image,
     pub background: Color,
+    pub atmosphere: Option<Atmosphere>,
+    /// How long it took to build the acceleration structure for `world`.
+    pub build_time: Duration,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
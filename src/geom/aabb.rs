@@ -5,6 +5,27 @@ use std::ops::{Range, RangeInclusive};
 
 use super::Vec3;
 
+/// A ray with the inverse of its direction worked out ahead of time, for
+/// testing it against many boxes.
+#[derive(Clone, Copy)]
+pub struct SlabRay {
+    pub origin: Point3,
+    pub inv_direction: Vec3,
+}
+
+impl From<Ray> for SlabRay {
+    fn from(ray: Ray) -> Self {
+        Self {
+            origin: ray.origin,
+            inv_direction: Vec3::new(
+                ray.direction.x().recip(),
+                ray.direction.y().recip(),
+                ray.direction.z().recip(),
+            ),
+        }
+    }
+}
+
 /// Axis-aligned bounding box
 #[derive(Clone, PartialEq, Debug)]
 pub struct Aabb {
@@ -32,21 +53,51 @@ impl Aabb {
         Some(Self { min, max })
     }
 
-    pub fn intersect(&self, ray: Ray, t_range: Range<f64>) -> bool {
+    /// The smallest box that contains both this one and `other`.
+    #[must_use]
+    pub fn union(&self, other: &Self) -> Self {
+        Self {
+            min: Point3::new(
+                self.min.x().min(other.min.x()),
+                self.min.y().min(other.min.y()),
+                self.min.z().min(other.min.z()),
+            ),
+            max: Point3::new(
+                self.max.x().max(other.max.x()),
+                self.max.y().max(other.max.y()),
+                self.max.z().max(other.max.z()),
+            ),
+        }
+    }
+
+    /// Narrows `t_range` down to the part of the ray that is inside the box.
+    pub fn clip(&self, ray: Ray, t_range: Range<f64>) -> Option<Range<f64>> {
+        self.clip_slab(&SlabRay::from(ray), t_range)
+    }
+
+    /// Like `clip`, for a ray that is tested against many boxes.
+    pub fn clip_slab(&self, ray: &SlabRay, t_range: Range<f64>) -> Option<Range<f64>> {
+        let mut t_min = t_range.start;
+        let mut t_max = t_range.end;
         for a in 0..3 {
-            let inv_dir = ray.direction[a].recip();
+            let inv_dir = ray.inv_direction[a];
             let mut t0 = (self.min[a] - ray.origin[a]) * inv_dir;
             let mut t1 = (self.max[a] - ray.origin[a]) * inv_dir;
             if inv_dir < 0.0 {
                 std::mem::swap(&mut t0, &mut t1);
             }
-            let t_min = t0.max(t_range.start);
-            let t_max = t1.min(t_range.end);
+            t_min = t0.max(t_min);
+            t_max = t1.min(t_max);
             if t_max <= t_min {
-                return false;
+                return None;
             }
         }
-        true
+        Some(t_min..t_max)
+    }
+
+    pub fn surface_area(&self) -> f64 {
+        let span = self.span();
+        2.0 * (span.x() * span.y() + span.y() * span.z() + span.z() * span.x())
     }
 
     pub fn span(&self) -> Vec3 {
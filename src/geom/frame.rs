@@ -0,0 +1,68 @@
+use super::{Aabb, Point3, PointCloud, Ray, Vec3};
+
+/// An orthonormal coordinate system positioned at `origin`, with its local Z
+/// axis pointing along a chosen direction.
+#[derive(Clone, Debug)]
+pub struct Frame {
+    pub origin: Point3,
+    u: Vec3,
+    v: Vec3,
+    w: Vec3,
+}
+
+impl Frame {
+    pub fn new(origin: Point3, axis: Vec3) -> Self {
+        let w = axis.unit_vector();
+        let a = if w.x().abs() > 0.9 {
+            Vec3::new(0.0, 1.0, 0.0)
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let v = w.cross(a).unit_vector();
+        let u = w.cross(v);
+        Self { origin, u, v, w }
+    }
+
+    /// Like `new`, but with the local X axis as close to `tangent` as it can
+    /// be while staying perpendicular to `axis`.
+    pub fn with_tangent(origin: Point3, axis: Vec3, tangent: Vec3) -> Self {
+        let w = axis.unit_vector();
+        let u = tangent - tangent.project(w);
+        if u.is_near_zero() {
+            return Self::new(origin, axis);
+        }
+        let u = u.unit_vector();
+        let v = w.cross(u);
+        Self { origin, u, v, w }
+    }
+
+    pub fn vector_to_local(&self, a: Vec3) -> Vec3 {
+        Vec3::new(a.dot(self.u), a.dot(self.v), a.dot(self.w))
+    }
+
+    pub fn vector_to_world(&self, a: Vec3) -> Vec3 {
+        a.x() * self.u + a.y() * self.v + a.z() * self.w
+    }
+
+    pub fn point_to_local(&self, p: Point3) -> Point3 {
+        self.vector_to_local(p - self.origin)
+    }
+
+    pub fn ray_to_local(&self, ray: Ray) -> Ray {
+        Ray::new(
+            self.point_to_local(ray.origin),
+            self.vector_to_local(ray.direction),
+            ray.time,
+        )
+    }
+
+    /// Converts a box in local coordinates to a world space box that contains it.
+    pub fn bounding_box(&self, local: &Aabb) -> Option<Aabb> {
+        local
+            .corners()
+            .into_iter()
+            .map(|c| self.origin + self.vector_to_world(c))
+            .collect::<PointCloud>()
+            .bounding_box()
+    }
+}
@@ -1,11 +1,15 @@
 mod aabb;
 mod axis;
+mod frame;
 mod ray;
+mod transform;
 mod vec3;
 mod point_cloud;
 
-pub use aabb::Aabb;
+pub use aabb::{Aabb, SlabRay};
 pub use axis::Axis;
+pub use frame::Frame;
 pub use ray::Ray;
+pub use transform::{Pose, Transform};
 pub use vec3::{Color, Point3, Vec3};
-pub use point_cloud::PointCloud;
\ No newline at end of file
+pub use point_cloud::PointCloud;
@@ -0,0 +1,213 @@
+use super::{Aabb, Axis, Point3, PointCloud, Ray, Vec3};
+use crate::interpolate::lerp;
+
+type Matrix = [[f64; 3]; 3];
+
+const IDENTITY: Matrix = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+
+/// An affine transformation, made of a linear part followed by a translation.
+/// The inverse of the linear part is kept alongside it, so that rays can be
+/// moved into object space cheaply.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Transform {
+    linear: Matrix,
+    inverse: Matrix,
+    offset: Vec3,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self {
+            linear: IDENTITY,
+            inverse: IDENTITY,
+            offset: Vec3::default(),
+        }
+    }
+}
+
+impl Transform {
+    pub fn translate(offset: Vec3) -> Self {
+        Self {
+            offset,
+            ..Self::default()
+        }
+    }
+
+    /// Rotates counter-clockwise by `theta` radians around `axis`.
+    pub fn rotate(axis: Axis, theta: f64) -> Self {
+        let (s, c) = theta.sin_cos();
+        let (i, j) = match axis {
+            Axis::X => (1, 2),
+            Axis::Y => (2, 0),
+            Axis::Z => (0, 1),
+        };
+        let mut linear = IDENTITY;
+        linear[i][i] = c;
+        linear[i][j] = -s;
+        linear[j][i] = s;
+        linear[j][j] = c;
+        Self {
+            linear,
+            inverse: transpose(&linear),
+            offset: Vec3::default(),
+        }
+    }
+
+    pub fn scale(factors: Vec3) -> Self {
+        let mut linear = IDENTITY;
+        let mut inverse = IDENTITY;
+        for axis in 0..3 {
+            linear[axis][axis] = factors[axis];
+            inverse[axis][axis] = factors[axis].recip();
+        }
+        Self {
+            linear,
+            inverse,
+            offset: Vec3::default(),
+        }
+    }
+
+    /// Returns a transform that applies `self` and then `next`.
+    #[must_use]
+    pub fn then(&self, next: &Transform) -> Self {
+        Self {
+            linear: multiply(&next.linear, &self.linear),
+            inverse: multiply(&self.inverse, &next.inverse),
+            offset: apply(&next.linear, self.offset) + next.offset,
+        }
+    }
+
+    pub fn point(&self, p: Point3) -> Point3 {
+        apply(&self.linear, p) + self.offset
+    }
+
+    pub fn vector(&self, v: Vec3) -> Vec3 {
+        apply(&self.linear, v)
+    }
+
+    /// Transforms a surface normal, which needs the inverse transpose of the
+    /// linear part to stay perpendicular to the surface under scaling.
+    pub fn normal(&self, n: Vec3) -> Vec3 {
+        apply(&transpose(&self.inverse), n).unit_vector()
+    }
+
+    pub fn inverse_point(&self, p: Point3) -> Point3 {
+        apply(&self.inverse, p - self.offset)
+    }
+
+    pub fn inverse_vector(&self, v: Vec3) -> Vec3 {
+        apply(&self.inverse, v)
+    }
+
+    /// Moves a ray into the space before this transform. Distances along the
+    /// ray are unchanged.
+    pub fn inverse_ray(&self, ray: Ray) -> Ray {
+        Ray::new(
+            self.inverse_point(ray.origin),
+            self.inverse_vector(ray.direction),
+            ray.time,
+        )
+    }
+
+    pub fn bounding_box(&self, bbox: &Aabb) -> Option<Aabb> {
+        bbox.corners()
+            .into_iter()
+            .map(|c| self.point(c))
+            .collect::<PointCloud>()
+            .bounding_box()
+    }
+}
+
+/// A transform broken down into scale, rotation and translation, so that it
+/// can be interpolated smoothly. It scales, then rotates around X, Y and Z (in
+/// radians), then translates.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Pose {
+    pub scale: Vec3,
+    pub rotation: Vec3,
+    pub offset: Vec3,
+}
+
+impl Default for Pose {
+    fn default() -> Self {
+        Self {
+            scale: Vec3::new(1.0, 1.0, 1.0),
+            rotation: Vec3::default(),
+            offset: Vec3::default(),
+        }
+    }
+}
+
+impl Pose {
+    pub fn transform(&self) -> Transform {
+        let mut transform = Transform::scale(self.scale);
+        for axis in [Axis::X, Axis::Y, Axis::Z] {
+            if self.rotation[axis] != 0.0 {
+                transform = transform.then(&Transform::rotate(axis, self.rotation[axis]));
+            }
+        }
+        transform.then(&Transform::translate(self.offset))
+    }
+
+    #[must_use]
+    pub fn lerp(&self, other: &Pose, t: f64) -> Pose {
+        Pose {
+            scale: lerp(self.scale, other.scale, t),
+            rotation: lerp(self.rotation, other.rotation, t),
+            offset: lerp(self.offset, other.offset, t),
+        }
+    }
+}
+
+fn apply(m: &Matrix, v: Vec3) -> Vec3 {
+    Vec3::new(
+        m[0][0] * v.x() + m[0][1] * v.y() + m[0][2] * v.z(),
+        m[1][0] * v.x() + m[1][1] * v.y() + m[1][2] * v.z(),
+        m[2][0] * v.x() + m[2][1] * v.y() + m[2][2] * v.z(),
+    )
+}
+
+fn multiply(a: &Matrix, b: &Matrix) -> Matrix {
+    let mut out = [[0.0; 3]; 3];
+    for (i, row) in out.iter_mut().enumerate() {
+        for (j, cell) in row.iter_mut().enumerate() {
+            *cell = (0..3).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    out
+}
+
+fn transpose(m: &Matrix) -> Matrix {
+    let mut out = [[0.0; 3]; 3];
+    for (i, row) in out.iter_mut().enumerate() {
+        for (j, cell) in row.iter_mut().enumerate() {
+            *cell = m[j][i];
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::geom::{Axis, Point3, Transform, Vec3};
+
+    fn assert_near(a: Vec3, b: Vec3) {
+        assert!((a - b).length() < 1e-9, "{a:?} != {b:?}");
+    }
+
+    #[test]
+    fn test_compose() {
+        let transform = Transform::scale(Vec3::new(2.0, 1.0, 1.0))
+            .then(&Transform::rotate(Axis::Y, 90_f64.to_radians()))
+            .then(&Transform::translate(Vec3::new(0.0, 0.0, 1.0)));
+        let p = Point3::new(1.0, 2.0, 3.0);
+
+        // x is doubled, then x goes to -z and z goes to x
+        assert_near(transform.point(p), Point3::new(3.0, 2.0, -1.0));
+        assert_near(transform.inverse_point(transform.point(p)), p);
+        assert_near(
+            transform.normal(Vec3::new(1.0, 0.0, 0.0)),
+            Vec3::new(0.0, 0.0, -1.0),
+        );
+    }
+}
@@ -91,6 +91,11 @@ impl Vec3 {
         r_out_perp + r_out_parallel
     }
 
+    /// The largest magnitude of any of the components.
+    pub fn max_abs(self) -> f64 {
+        self.0.abs().max(self.1.abs()).max(self.2.abs())
+    }
+
     pub fn unit_vector(self) -> Vec3 {
         self / self.length()
     }
@@ -0,0 +1,97 @@
+use std::{ops::Range, sync::Arc};
+
+use crate::{
+    geom::{Aabb, Frame, Point3, Ray},
+    material::Material,
+    polynomial::solve_quadratic,
+};
+
+use super::{
+    quadric::{azimuth, LocalHit},
+    HitRecord, Hittable,
+};
+
+/// A cylinder from `base` to `top` with hemispherical ends.
+#[derive(Clone)]
+pub struct Capsule {
+    frame: Frame,
+    height: f64,
+    radius: f64,
+    material: Arc<dyn Material>,
+}
+
+impl Capsule {
+    pub fn new(base: Point3, top: Point3, radius: f64, material: Arc<dyn Material>) -> Self {
+        Self {
+            frame: Frame::new(base, top - base),
+            height: (top - base).length(),
+            radius,
+            material,
+        }
+    }
+
+    /// Maps a point on the surface so that `v` runs from the bottom of the
+    /// lower hemisphere to the top of the upper one.
+    fn local_hit(&self, t: f64, p: Point3, center: Point3) -> LocalHit {
+        LocalHit {
+            t,
+            normal: (p - center) / self.radius,
+            u: azimuth(p),
+            v: (p.z() + self.radius) / (self.height + 2.0 * self.radius),
+        }
+    }
+}
+
+impl Hittable for Capsule {
+    fn hit(&self, ray: Ray, t_range: Range<f64>) -> Option<HitRecord> {
+        let local = self.frame.ray_to_local(ray);
+        let o = local.origin;
+        let d = local.direction;
+        let r2 = self.radius.powi(2);
+
+        let mut hits: Vec<LocalHit> = solve_quadratic(
+            d.x().powi(2) + d.y().powi(2),
+            2.0 * (o.x() * d.x() + o.y() * d.y()),
+            o.x().powi(2) + o.y().powi(2) - r2,
+        )
+        .into_iter()
+        .filter_map(|t| {
+            let p = local.along(t);
+            (0.0..=self.height)
+                .contains(&p.z())
+                .then(|| self.local_hit(t, p, Point3::new(0.0, 0.0, p.z())))
+        })
+        .collect();
+
+        // each hemisphere only covers the half of its sphere beyond the ends
+        // of the cylinder
+        for (center, side) in [
+            (Point3::default(), -1.0),
+            (Point3::new(0.0, 0.0, self.height), 1.0),
+        ] {
+            let oc = o - center;
+            hits.extend(
+                solve_quadratic(
+                    d.length_squared(),
+                    2.0 * oc.dot(d),
+                    oc.length_squared() - r2,
+                )
+                .into_iter()
+                .filter_map(|t| {
+                    let p = local.along(t);
+                    ((p.z() - center.z()) * side > 0.0).then(|| self.local_hit(t, p, center))
+                }),
+            );
+        }
+
+        LocalHit::closest(hits, t_range, ray, &self.frame, &self.material)
+    }
+
+    fn bounding_box(&self, _time_range: Range<f64>) -> Option<Aabb> {
+        let r = self.radius;
+        self.frame.bounding_box(&Aabb::new(
+            Point3::new(-r, -r, -r),
+            Point3::new(r, r, self.height + r),
+        ))
+    }
+}
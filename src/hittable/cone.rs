@@ -0,0 +1,108 @@
+use std::{ops::Range, sync::Arc};
+
+use crate::{
+    geom::{Aabb, Frame, Point3, Ray, Vec3},
+    material::Material,
+    polynomial::solve_quadratic,
+};
+
+use super::{
+    quadric::{azimuth, disk_hit, LocalHit},
+    HitRecord, Hittable,
+};
+
+/// A cone running from `base` to `top`. The radius changes linearly along its
+/// length, so a non-zero `top_radius` makes a truncated cone.
+#[derive(Clone)]
+pub struct Cone {
+    frame: Frame,
+    height: f64,
+    base_radius: f64,
+    top_radius: f64,
+    capped: bool,
+    material: Arc<dyn Material>,
+}
+
+impl Cone {
+    pub fn new(
+        base: Point3,
+        top: Point3,
+        base_radius: f64,
+        top_radius: f64,
+        capped: bool,
+        material: Arc<dyn Material>,
+    ) -> Self {
+        Self {
+            frame: Frame::new(base, top - base),
+            height: (top - base).length(),
+            base_radius,
+            top_radius,
+            capped,
+            material,
+        }
+    }
+
+    fn radius_at(&self, z: f64) -> f64 {
+        self.base_radius + self.slope() * z
+    }
+
+    fn slope(&self) -> f64 {
+        (self.top_radius - self.base_radius) / self.height
+    }
+}
+
+impl Hittable for Cone {
+    fn hit(&self, ray: Ray, t_range: Range<f64>) -> Option<HitRecord> {
+        let local = self.frame.ray_to_local(ray);
+        let o = local.origin;
+        let d = local.direction;
+        let k = self.slope();
+        let r = self.radius_at(o.z());
+
+        let mut hits: Vec<LocalHit> = solve_quadratic(
+            d.x().powi(2) + d.y().powi(2) - (k * d.z()).powi(2),
+            2.0 * (o.x() * d.x() + o.y() * d.y() - r * k * d.z()),
+            o.x().powi(2) + o.y().powi(2) - r.powi(2),
+        )
+        .into_iter()
+        .filter_map(|t| {
+            let p = local.along(t);
+            (0.0..=self.height).contains(&p.z()).then(|| LocalHit {
+                t,
+                normal: Vec3::new(p.x(), p.y(), -self.radius_at(p.z()) * k).unit_vector(),
+                u: azimuth(p),
+                v: p.z() / self.height,
+            })
+        })
+        .collect();
+
+        if self.capped {
+            if self.base_radius > 0.0 {
+                hits.extend(disk_hit(
+                    local,
+                    0.0,
+                    self.base_radius,
+                    Vec3::new(0.0, 0.0, -1.0),
+                ));
+            }
+            if self.top_radius > 0.0 {
+                hits.extend(disk_hit(
+                    local,
+                    self.height,
+                    self.top_radius,
+                    Vec3::new(0.0, 0.0, 1.0),
+                ));
+            }
+        }
+
+        LocalHit::closest(hits, t_range, ray, &self.frame, &self.material)
+    }
+
+    fn bounding_box(&self, _time_range: Range<f64>) -> Option<Aabb> {
+        let r = self.base_radius.max(self.top_radius);
+        self.frame.bounding_box(&Aabb::new(
+            Point3::new(-r, -r, 0.0),
+            Point3::new(r, r, self.height),
+        ))
+    }
+}
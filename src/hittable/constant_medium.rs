@@ -2,7 +2,7 @@ use std::{ops::Range, sync::Arc};
 use rand::Rng;
 use crate::{
     geom::{Aabb, Vec3},
-    texture::{Isotropic, Texture},
+    material::Material,
 };
 use super::{HitRecord, Hittable};
 
@@ -10,15 +10,19 @@ use super::{HitRecord, Hittable};
 pub struct ConstantMedium {
     boundary: Box<dyn Hittable>,
     neg_inv_density: f64,
-    phase_function: Arc<Isotropic>,
+    phase_function: Arc<dyn Material>,
 }
 
 impl ConstantMedium {
-    pub fn new(boundary: Box<dyn Hittable>, density: f64, texture: Box<dyn Texture>) -> Self {
+    pub fn new(
+        boundary: Box<dyn Hittable>,
+        density: f64,
+        phase_function: Arc<dyn Material>,
+    ) -> Self {
         Self {
             boundary,
             neg_inv_density: -density.recip(),
-            phase_function: Arc::new(Isotropic { albedo: texture }),
+            phase_function,
         }
     }
 }
@@ -26,56 +30,93 @@ impl ConstantMedium {
 impl Hittable for ConstantMedium {
     fn hit(&self, ray: crate::geom::Ray, t_range: Range<f64>) -> Option<super::HitRecord> {
         let mut rng = rand::thread_rng();
-        let enable_debug = false;
-        let debug = enable_debug && rng.gen_bool(0.00001);
-
-        let mut rec1 = self.boundary.hit(ray, -f64::INFINITY..f64::INFINITY)?;
-        let mut rec2 = self.boundary.hit(ray, (rec1.t + 0.0001)..f64::INFINITY)?;
-
-        if debug { dbg!(&rec1.t, &rec2.t); }
-
-        if rec1.t < t_range.start {
-            rec1.t = t_range.start;
-        }
-        if rec2.t > t_range.end {
-            rec2.t = t_range.end;
-        }
+        let ray_length = ray.direction.length();
 
-        if rec1.t >= rec2.t {
-            return None;
+        // the distance is memoryless, so it can be spent across every stretch
+        // of the medium that the ray passes through in turn
+        let mut hit_distance = self.neg_inv_density * (1.0 - rng.gen::<f64>()).ln();
+        for span in spans_inside(self.boundary.as_ref(), ray, t_range) {
+            let distance_inside_boundary = (span.end - span.start) * ray_length;
+            if hit_distance <= distance_inside_boundary {
+                let t = span.start + hit_distance / ray_length;
+                return Some(HitRecord::new(
+                    ray.along(t),
+                    t,
+                    ray,
+                    Vec3::new(1.0, 0.0, 0.0),
+                    self.phase_function.clone(),
+                    0.0,
+                    0.0,
+                ));
+            }
+            hit_distance -= distance_inside_boundary;
         }
+        None
+    }
 
-        if rec1.t < 0.0 {
-            rec1.t = 0.0;
-        }
+    fn bounding_box(&self, time_range: Range<f64>) -> Option<Aabb> {
+        self.boundary.bounding_box(time_range)
+    }
 
-        let ray_length = ray.direction.length();
-        let distance_inside_boundary = (rec2.t - rec1.t) * ray_length;
-        let hit_distance = self.neg_inv_density * rng.gen::<f64>().log10();
+    fn refit(&mut self, time_range: Range<f64>) {
+        self.boundary.refit(time_range);
+    }
+}
 
-        if debug { dbg!(&hit_distance, &distance_inside_boundary); }
+/// The parts of `t_range` along `ray` that are inside `boundary`, in order.
+/// Since these come from counting the boundary's entries and exits, they are
+/// right for non-convex boundaries and for rays that start inside.
+pub(super) fn spans_inside(
+    boundary: &dyn Hittable,
+    ray: crate::geom::Ray,
+    t_range: Range<f64>,
+) -> impl Iterator<Item = Range<f64>> {
+    boundary
+        .intervals(ray)
+        .into_iter()
+        .map(move |interval| {
+            interval.enter.t.max(t_range.start)..interval.exit.t.min(t_range.end)
+        })
+        .filter(|span| span.start < span.end)
+}
 
-        if hit_distance > distance_inside_boundary {
-            return None;
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        geom::{Point3, Ray},
+        hittable::{HittableList, Sphere},
+        texture::{Isotropic, SolidColor},
+    };
 
-        let t = rec1.t + hit_distance / ray_length;
-        let p = ray.along(t);
+    #[test]
+    fn test_non_convex_boundary() {
+        let material: Arc<dyn Material> = Arc::new(Isotropic {
+            albedo: Box::new(SolidColor(Vec3::default())),
+        });
+        let sphere = |x| -> Box<dyn Hittable> {
+            Box::new(Sphere {
+                center: Point3::new(x, 0.0, 0.0),
+                radius: 1.0,
+                material: material.clone(),
+            })
+        };
+        // two separate blobs of fog, so thick that rays scatter right away
+        let boundary = HittableList::new(vec![sphere(0.0), sphere(4.0)]);
+        let medium = ConstantMedium::new(Box::new(boundary), 1e6, material.clone());
 
-        if debug { dbg!(&t, &p); }
+        // starting inside the first blob
+        let ray = Ray::new(Point3::new(0.5, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0), 0.0);
+        let hit = medium.hit(ray, 0.001..f64::INFINITY).unwrap();
+        assert!((hit.t - 0.001).abs() < 1e-3);
 
-        Some(HitRecord::new(
-            p,
-            t,
-            ray,
-            Vec3::new(1.0, 0.0, 0.0),
-            self.phase_function.clone(),
-            0.0,
-            0.0,
-        ))
-    }
+        // starting in the gap between them
+        let ray = Ray::new(Point3::new(2.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0), 0.0);
+        let hit = medium.hit(ray, 0.001..f64::INFINITY).unwrap();
+        assert!((hit.t - 1.0).abs() < 1e-3);
 
-    fn bounding_box(&self, time_range: Range<f64>) -> Option<Aabb> {
-        self.boundary.bounding_box(time_range)
+        // leaving both behind
+        let ray = Ray::new(Point3::new(5.5, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0), 0.0);
+        assert!(medium.hit(ray, 0.001..f64::INFINITY).is_none());
     }
 }
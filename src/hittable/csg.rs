@@ -0,0 +1,222 @@
+use std::ops::Range;
+
+use crate::geom::{Aabb, Point3, Ray};
+
+use super::{HitRecord, Hittable, Interval};
+
+#[derive(Clone, Copy, Debug)]
+pub enum CsgOp {
+    Union,
+    Intersection,
+    Difference,
+}
+
+impl CsgOp {
+    fn contains(self, in_left: bool, in_right: bool) -> bool {
+        match self {
+            CsgOp::Union => in_left || in_right,
+            CsgOp::Intersection => in_left && in_right,
+            CsgOp::Difference => in_left && !in_right,
+        }
+    }
+}
+
+/// Constructive solid geometry, combining two closed objects into a new
+/// closed object.
+#[derive(Clone)]
+pub struct Csg {
+    pub op: CsgOp,
+    pub left: Box<dyn Hittable>,
+    pub right: Box<dyn Hittable>,
+}
+
+impl Csg {
+    /// Combines two sorted lists of intervals along the same ray.
+    pub fn combine(op: CsgOp, left: Vec<Interval>, right: Vec<Interval>) -> Vec<Interval> {
+        // (is_left, entering, hit)
+        let mut events: Vec<(bool, bool, HitRecord)> = left
+            .into_iter()
+            .flat_map(|i| [(true, true, i.enter), (true, false, i.exit)])
+            .chain(
+                right
+                    .into_iter()
+                    .flat_map(|i| [(false, true, i.enter), (false, false, i.exit)]),
+            )
+            .collect();
+        events.sort_by(|a, b| a.2.t.total_cmp(&b.2.t));
+
+        let mut in_left = false;
+        let mut in_right = false;
+        let mut enter = None;
+        let mut intervals = vec![];
+
+        for (is_left, entering, mut hit) in events {
+            let was_inside = op.contains(in_left, in_right);
+            if is_left {
+                in_left = entering;
+            } else {
+                in_right = entering;
+            }
+            let inside = op.contains(in_left, in_right);
+
+            if inside != was_inside {
+                // The normal always faces the ray, so only the face needs
+                // fixing up, such as when leaving a subtracted object means
+                // entering the result.
+                hit.front_face = inside;
+                if inside {
+                    enter = Some(hit);
+                } else if let Some(enter) = enter.take() {
+                    intervals.push(Interval { enter, exit: hit });
+                }
+            }
+        }
+
+        intervals
+    }
+}
+
+impl Hittable for Csg {
+    fn hit(&self, ray: Ray, t_range: Range<f64>) -> Option<HitRecord> {
+        self.intervals(ray)
+            .into_iter()
+            .flat_map(|i| [i.enter, i.exit])
+            .find(|hit| t_range.contains(&hit.t))
+    }
+
+    fn refit(&mut self, time_range: Range<f64>) {
+        self.left.refit(time_range.clone());
+        self.right.refit(time_range);
+    }
+
+    fn bounding_box(&self, time_range: Range<f64>) -> Option<Aabb> {
+        let left = self.left.bounding_box(time_range.clone());
+        let right = self.right.bounding_box(time_range);
+        match self.op {
+            CsgOp::Union => Aabb::surrounding(&[&left?, &right?]),
+            CsgOp::Intersection => {
+                let (left, right) = (left?, right?);
+                let min = Point3::new(
+                    left.min.x().max(right.min.x()),
+                    left.min.y().max(right.min.y()),
+                    left.min.z().max(right.min.z()),
+                );
+                let max = Point3::new(
+                    left.max.x().min(right.max.x()),
+                    left.max.y().min(right.max.y()),
+                    left.max.z().min(right.max.z()),
+                );
+                Some(Aabb::new(min, max))
+            }
+            CsgOp::Difference => left,
+        }
+    }
+
+    fn intervals(&self, ray: Ray) -> Vec<Interval> {
+        Csg::combine(self.op, self.left.intervals(ray), self.right.intervals(ray))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Csg, CsgOp};
+    use crate::{
+        geom::{Color, Point3, Ray, Vec3},
+        hittable::{Cylinder, Hittable, Sphere},
+        material::{Lambertian, Material},
+        texture::SolidColor,
+    };
+    use std::sync::Arc;
+
+    fn sphere(x: f64, radius: f64) -> Box<dyn Hittable> {
+        let material: Arc<dyn Material> = Arc::new(Lambertian {
+            albedo: Box::new(SolidColor(Color::white())),
+        });
+        Box::new(Sphere {
+            center: Point3::new(x, 0.0, 0.0),
+            radius,
+            material,
+        })
+    }
+
+    #[test]
+    fn test_difference() {
+        let csg = Csg {
+            op: CsgOp::Difference,
+            left: sphere(0.0, 2.0),
+            right: sphere(0.0, 1.0),
+        };
+        let ray = Ray::new(Point3::new(-5.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0), 0.0);
+        let intervals: Vec<_> = csg
+            .intervals(ray)
+            .into_iter()
+            .map(|i| (i.enter.t, i.enter.front_face, i.exit.t, i.exit.front_face))
+            .collect();
+        assert_eq!(
+            intervals,
+            vec![(3.0, true, 4.0, false), (6.0, true, 7.0, false)]
+        );
+
+        // starting in the hollow middle, the first thing hit is the inside wall
+        let hit = csg.hit(
+            Ray::new(Point3::default(), Vec3::new(1.0, 0.0, 0.0), 0.0),
+            0.001..f64::INFINITY,
+        );
+        assert_eq!(hit.map(|h| (h.t, h.front_face)), Some((1.0, true)));
+    }
+
+    #[test]
+    fn test_intersection() {
+        let csg = Csg {
+            op: CsgOp::Intersection,
+            left: sphere(0.0, 2.0),
+            right: sphere(2.0, 2.0),
+        };
+        let ray = Ray::new(Point3::new(-5.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0), 0.0);
+        let intervals: Vec<_> = csg
+            .intervals(ray)
+            .into_iter()
+            .map(|i| (i.enter.t, i.exit.t))
+            .collect();
+        assert_eq!(intervals, vec![(5.0, 7.0)]);
+    }
+
+    #[test]
+    fn test_scale() {
+        let material: Arc<dyn Material> = Arc::new(Lambertian {
+            albedo: Box::new(SolidColor(Color::white())),
+        });
+        // a tube, walking through the default intervals of its cylinders,
+        // both far smaller and far larger than a unit
+        for scale in [1e-6, 1e6] {
+            let cylinder = |radius: f64| -> Box<dyn Hittable> {
+                Box::new(Cylinder::new(
+                    Point3::new(0.0, 0.0, -scale),
+                    Point3::new(0.0, 0.0, scale),
+                    radius * scale,
+                    true,
+                    material.clone(),
+                ))
+            };
+            let csg = Csg {
+                op: CsgOp::Difference,
+                left: cylinder(1.0),
+                right: cylinder(0.5),
+            };
+            let ray = Ray::new(
+                Point3::new(-5.0 * scale, 0.0, 0.0),
+                Vec3::new(1.0, 0.0, 0.0),
+                0.0,
+            );
+            let intervals: Vec<_> = csg
+                .intervals(ray)
+                .into_iter()
+                .flat_map(|i| [i.enter.t / scale, i.exit.t / scale])
+                .collect();
+            assert_eq!(intervals.len(), 4, "at scale {scale}");
+            for (t, expected) in intervals.into_iter().zip([4.0, 4.5, 5.5, 6.0]) {
+                assert!((t - expected).abs() < 1e-6, "at scale {scale}");
+            }
+        }
+    }
+}
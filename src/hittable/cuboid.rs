@@ -1,48 +1,115 @@
 use crate::{
-    geom::{Aabb, Axis, Point3, Vec3, Ray},
-    hittable::{AxisAlignedRect, Hittable},
+    geom::{Aabb, Axis, Point3, Ray, Vec3},
+    hittable::{HitRecord, Hittable, Interval},
     material::Material,
 };
 use std::{ops::Range, sync::Arc};
 
-use super::HittableList;
-
 #[derive(Clone)]
 pub struct Cuboid {
     center: Point3,
     half_size: Vec3,
-    sides: HittableList,
+    material: Arc<dyn Material>,
 }
 
 impl Cuboid {
     pub fn new(center: Point3, size: Vec3, material: &Arc<dyn Material>) -> Self {
-        let half_size = size / 2.0;
-
-        let sides: HittableList = itertools::iproduct!([Axis::X, Axis::Y, Axis::Z], [-1.0, 1.0])
-            .into_iter()
-            .map(|(axis, mult)| AxisAlignedRect {
-                axis,
-                center: center + half_size.project(axis.into()) * mult,
-                width: size[axis.next()],
-                height: size[axis.prev()],
-                material: material.clone(),
-            })
-            .collect();
-
         Self {
             center,
-            half_size,
-            sides,
+            half_size: size / 2.0,
+            material: material.clone(),
         }
     }
+
+    /// Builds a hit record where the ray crosses `face`.
+    fn face_record(&self, ray: Ray, face: Face) -> HitRecord {
+        let Face { t, axis, side } = face;
+        let p = ray.along(t);
+        let min = self.center - self.half_size;
+        let span = self.half_size * 2.0;
+        let u = (p[axis.next()] - min[axis.next()]) / span[axis.next()];
+        let v = (p[axis.prev()] - min[axis.prev()]) / span[axis.prev()];
+        let outward_normal = Vec3::from(axis) * side;
+        HitRecord::new(p, t, ray, outward_normal, self.material.clone(), u, v)
+    }
+
+    /// Slab test, keeping track of which face the line through the ray
+    /// crosses last when entering and first when exiting.
+    fn crossings(&self, ray: Ray) -> Option<(Face, Face)> {
+        let mut enter = Face {
+            t: -f64::INFINITY,
+            axis: Axis::X,
+            side: 0.0,
+        };
+        let mut exit = Face {
+            t: f64::INFINITY,
+            axis: Axis::X,
+            side: 0.0,
+        };
+
+        for axis in [Axis::X, Axis::Y, Axis::Z] {
+            let inv_dir = ray.direction[axis].recip();
+            let near_side = -inv_dir.signum();
+            let t_near =
+                (self.center[axis] + near_side * self.half_size[axis] - ray.origin[axis]) * inv_dir;
+            let t_far =
+                (self.center[axis] - near_side * self.half_size[axis] - ray.origin[axis]) * inv_dir;
+            if t_near > enter.t {
+                enter = Face {
+                    t: t_near,
+                    axis,
+                    side: near_side,
+                };
+            }
+            if t_far < exit.t {
+                exit = Face {
+                    t: t_far,
+                    axis,
+                    side: -near_side,
+                };
+            }
+        }
+
+        (enter.t < exit.t && enter.t.is_finite() && exit.t.is_finite()).then_some((enter, exit))
+    }
+}
+
+/// Where a ray crosses one of the faces of a cuboid.
+#[derive(Clone, Copy)]
+struct Face {
+    t: f64,
+    axis: Axis,
+    side: f64,
 }
 
 impl Hittable for Cuboid {
     fn hit(&self, ray: Ray, t_range: Range<f64>) -> Option<super::HitRecord> {
-        self.sides.hit(ray, t_range)
+        let (enter, exit) = self.crossings(ray)?;
+        [enter, exit]
+            .into_iter()
+            .find(|face| t_range.contains(&face.t))
+            .map(|face| self.face_record(ray, face))
+    }
+
+    fn occluded(&self, ray: Ray, t_range: Range<f64>) -> bool {
+        self.crossings(ray)
+            .is_some_and(|(enter, exit)| t_range.contains(&enter.t) || t_range.contains(&exit.t))
     }
 
     fn bounding_box(&self, _time_range: Range<f64>) -> Option<Aabb> {
-        Some(Aabb::new(self.center - self.half_size, self.center + self.half_size))
+        Some(Aabb::new(
+            self.center - self.half_size,
+            self.center + self.half_size,
+        ))
+    }
+
+    fn intervals(&self, ray: Ray) -> Vec<Interval> {
+        self.crossings(ray)
+            .map(|(enter, exit)| Interval {
+                enter: self.face_record(ray, enter),
+                exit: self.face_record(ray, exit),
+            })
+            .into_iter()
+            .collect()
     }
 }
@@ -0,0 +1,367 @@
+use anyhow::{anyhow, Context, Result};
+use std::{fs, ops::Range, path::Path, sync::Arc};
+
+use crate::{
+    geom::{Aabb, Frame, Point3, PointCloud, Ray, Vec3},
+    interpolate::lerp,
+    material::Material,
+};
+
+use super::{BvhNode, HitRecord, Hittable};
+
+/// How the width of a curve is filled in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CurveShape {
+    /// A flat strip that always faces the ray, like a blade of grass seen
+    /// from afar.
+    Ribbon,
+    /// A round tube, shaded as if it were a thin cylinder.
+    #[default]
+    Tube,
+}
+
+/// A bundle of strands, such as hair, fur or grass. Each strand is a chain of
+/// cubic Bézier segments, whose width changes linearly along each segment.
+#[derive(Clone)]
+pub struct Curves {
+    segments: BvhNode,
+}
+
+/// One control point of a strand, and how wide the strand is there.
+pub type StrandPoint = (Point3, f64);
+
+impl Curves {
+    /// Builds curves from strands of control points. Each strand needs `3n +
+    /// 1` points: the ends of its segments, and two control points in between
+    /// each pair of ends.
+    pub fn new(
+        strands: &[Vec<StrandPoint>],
+        shape: CurveShape,
+        material: &Arc<dyn Material>,
+    ) -> Result<Self> {
+        let mut segments: Vec<Box<dyn Hittable>> = vec![];
+        for (idx, strand) in strands.iter().enumerate() {
+            anyhow::ensure!(
+                strand.len() >= 4 && strand.len() % 3 == 1,
+                "strand {} has {} points, but needs 3n + 1",
+                idx,
+                strand.len()
+            );
+            for points in strand.windows(4).step_by(3) {
+                segments.push(Box::new(Segment::new(
+                    [points[0].0, points[1].0, points[2].0, points[3].0],
+                    (points[0].1, points[3].1),
+                    shape,
+                    material.clone(),
+                )));
+            }
+        }
+        // the segments never move, so one tree serves for all time
+        Ok(Self {
+            segments: BvhNode::new(f64::NEG_INFINITY..f64::INFINITY, segments),
+        })
+    }
+
+    /// Loads strands from a text file. Each line holds one control point as
+    /// `x y z width`, and strands are separated by blank lines. Lines starting
+    /// with `#` are ignored.
+    pub fn load(path: &Path, shape: CurveShape, material: &Arc<dyn Material>) -> Result<Self> {
+        let text = fs::read_to_string(path)?;
+        let mut strands = vec![vec![]];
+        for (line_number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.starts_with('#') {
+                continue;
+            }
+            if line.is_empty() {
+                strands.push(vec![]);
+                continue;
+            }
+            let values = line
+                .split_whitespace()
+                .map(str::parse)
+                .collect::<Result<Vec<f64>, _>>()
+                .with_context(|| format!("parsing line {}", line_number + 1))?;
+            let [x, y, z, width] = values[..] else {
+                return Err(anyhow!(
+                    "line {} should have 4 numbers, but has {}",
+                    line_number + 1,
+                    values.len()
+                ));
+            };
+            strands
+                .last_mut()
+                .expect("there is always a strand")
+                .push((Point3::new(x, y, z), width));
+        }
+        strands.retain(|s| !s.is_empty());
+        Self::new(&strands, shape, material)
+    }
+}
+
+impl Hittable for Curves {
+    fn hit(&self, ray: Ray, t_range: Range<f64>) -> Option<HitRecord> {
+        self.segments.hit(ray, t_range)
+    }
+
+    fn occluded(&self, ray: Ray, t_range: Range<f64>) -> bool {
+        self.segments.occluded(ray, t_range)
+    }
+
+    fn bounding_box(&self, time_range: Range<f64>) -> Option<Aabb> {
+        self.segments.bounding_box(time_range)
+    }
+}
+
+/// A single cubic Bézier segment of a strand.
+#[derive(Clone)]
+struct Segment {
+    points: [Point3; 4],
+    widths: (f64, f64),
+    shape: CurveShape,
+    /// How many times to split the curve in half before treating the pieces
+    /// as straight lines.
+    depth: u32,
+    bounding_box: Aabb,
+    material: Arc<dyn Material>,
+}
+
+/// The closest point found so far while intersecting a segment, in the
+/// segment's ray space.
+struct CurveHit {
+    /// Distance along the ray's direction, which is the local Z axis.
+    z: f64,
+    /// Position along the curve.
+    u: f64,
+    /// Position across the width of the curve, from one edge to the other.
+    v: f64,
+    tangent: Vec3,
+    normal: Vec3,
+}
+
+impl Segment {
+    fn new(
+        points: [Point3; 4],
+        widths: (f64, f64),
+        shape: CurveShape,
+        material: Arc<dyn Material>,
+    ) -> Self {
+        let max_width = widths.0.max(widths.1);
+
+        // Split finely enough that a straight line is within a small fraction
+        // of the width from the real curve. This bound on the flatness
+        // follows PBRT.
+        let curvature = (0..2)
+            .map(|i| points[i] - 2.0 * points[i + 1] + points[i + 2])
+            .flat_map(|d| [d.x().abs(), d.y().abs(), d.z().abs()])
+            .fold(0.0, f64::max);
+        let epsilon = max_width / 20.0;
+        let depth = if curvature > 0.0 && epsilon > 0.0 {
+            ((std::f64::consts::SQRT_2 * 6.0 * curvature / (8.0 * epsilon)).log2() / 2.0)
+                .clamp(0.0, 10.0) as u32
+        } else {
+            0
+        };
+
+        let pad = Vec3::new(max_width, max_width, max_width) / 2.0;
+        let bounds = points
+            .iter()
+            .copied()
+            .collect::<PointCloud>()
+            .bounding_box();
+        let bounds = bounds.expect("a segment has control points");
+        Self {
+            points,
+            widths,
+            shape,
+            depth,
+            bounding_box: Aabb::new(bounds.min - pad, bounds.max + pad),
+            material,
+        }
+    }
+
+    fn width(&self, u: f64) -> f64 {
+        lerp(self.widths.0, self.widths.1, u)
+    }
+
+    /// Recursively splits the curve until the pieces are flat enough, and
+    /// keeps the closest piece that passes within half its width of the ray.
+    /// The ray starts at the origin and points along Z.
+    fn intersect(
+        &self,
+        points: [Point3; 4],
+        u_range: (f64, f64),
+        depth: u32,
+        z_range: &Range<f64>,
+        closest: &mut Option<CurveHit>,
+    ) {
+        let half_width = self.width(u_range.0).max(self.width(u_range.1)) / 2.0;
+        let z_max = closest.as_ref().map_or(z_range.end, |hit| hit.z);
+        let Some(bounds) = points
+            .iter()
+            .copied()
+            .collect::<PointCloud>()
+            .bounding_box()
+        else {
+            return;
+        };
+        if bounds.min.x() > half_width
+            || bounds.max.x() < -half_width
+            || bounds.min.y() > half_width
+            || bounds.max.y() < -half_width
+            || bounds.min.z() - half_width > z_max
+            || bounds.max.z() + half_width < z_range.start
+        {
+            return;
+        }
+
+        if depth > 0 {
+            let (first, second) = split_bezier(points);
+            let u_mid = 0.5 * (u_range.0 + u_range.1);
+            self.intersect(first, (u_range.0, u_mid), depth - 1, z_range, closest);
+            self.intersect(second, (u_mid, u_range.1), depth - 1, z_range, closest);
+            return;
+        }
+
+        // Treat this piece as a line, and find where it passes the ray.
+        let chord = points[3] - points[0];
+        let chord_length = chord.x().powi(2) + chord.y().powi(2);
+        let w = if chord_length > 0.0 {
+            (-(points[0].x() * chord.x() + points[0].y() * chord.y()) / chord_length)
+                .clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        let (center, tangent) = eval_bezier(points, w);
+        let u = lerp(u_range.0, u_range.1, w);
+        let half_width = self.width(u) / 2.0;
+        let distance = center.x().hypot(center.y());
+        if distance > half_width || tangent.is_near_zero() {
+            return;
+        }
+
+        // Directions across the curve: `facing` points back along the ray,
+        // and `offset` points from the middle of the curve towards the ray.
+        let tangent = tangent.unit_vector();
+        let view = Vec3::new(0.0, 0.0, -1.0);
+        let facing = (view - view.project(tangent)).unit_vector();
+        let side = tangent.cross(facing);
+        let across = -center.dot(side) / half_width;
+
+        let (z, normal) = match self.shape {
+            CurveShape::Ribbon => (center.z(), facing),
+            CurveShape::Tube => {
+                let bulge = (1.0 - across.powi(2)).max(0.0).sqrt();
+                (
+                    center.z() - bulge * half_width,
+                    bulge * facing + across * side,
+                )
+            }
+        };
+        if z < z_range.start || z > z_max {
+            return;
+        }
+
+        *closest = Some(CurveHit {
+            z,
+            u,
+            v: 0.5 * (across + 1.0),
+            tangent,
+            normal,
+        });
+    }
+}
+
+impl Hittable for Segment {
+    fn hit(&self, ray: Ray, t_range: Range<f64>) -> Option<HitRecord> {
+        let t_range = self.bounding_box.clip(ray, t_range)?;
+
+        let speed = ray.direction.length();
+        let frame = Frame::new(ray.origin, ray.direction);
+        let points = self.points.map(|p| frame.point_to_local(p));
+        let z_range = (t_range.start * speed)..(t_range.end * speed);
+
+        let mut closest = None;
+        self.intersect(points, (0.0, 1.0), self.depth, &z_range, &mut closest);
+        let hit = closest?;
+
+        let t = hit.z / speed;
+        let mut record = HitRecord::new(
+            ray.along(t),
+            t,
+            ray,
+            frame.vector_to_world(hit.normal),
+            self.material.clone(),
+            hit.u,
+            hit.v,
+        );
+        record.tangent = Some(frame.vector_to_world(hit.tangent));
+        // the flattened pieces are only within a fraction of the width of
+        // the real curve, and rays leaving it have to clear it entirely
+        record.error = record.error.max(self.width(hit.u));
+        Some(record)
+    }
+
+    fn bounding_box(&self, _time_range: Range<f64>) -> Option<Aabb> {
+        Some(self.bounding_box.clone())
+    }
+}
+
+/// Evaluates a cubic Bézier curve and its derivative.
+fn eval_bezier(p: [Point3; 4], u: f64) -> (Point3, Vec3) {
+    let a = [
+        lerp(p[0], p[1], u),
+        lerp(p[1], p[2], u),
+        lerp(p[2], p[3], u),
+    ];
+    let b = [lerp(a[0], a[1], u), lerp(a[1], a[2], u)];
+    (lerp(b[0], b[1], u), 3.0 * (b[1] - b[0]))
+}
+
+/// Splits a cubic Bézier curve in half with de Casteljau's algorithm.
+fn split_bezier(p: [Point3; 4]) -> ([Point3; 4], [Point3; 4]) {
+    let a = [
+        lerp(p[0], p[1], 0.5),
+        lerp(p[1], p[2], 0.5),
+        lerp(p[2], p[3], 0.5),
+    ];
+    let b = [lerp(a[0], a[1], 0.5), lerp(a[1], a[2], 0.5)];
+    let mid = lerp(b[0], b[1], 0.5);
+    ([p[0], a[0], b[0], mid], [mid, b[1], a[2], p[3]])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{material::Lambertian, texture::SolidColor};
+
+    #[test]
+    fn test_tube() {
+        let material: Arc<dyn Material> = Arc::new(Lambertian {
+            albedo: Box::new(SolidColor(Vec3::default())),
+        });
+        // a gently curving strand along X, 0.2 wide
+        let strand = vec![
+            (Point3::new(-1.0, 0.0, 0.0), 0.2),
+            (Point3::new(-0.3, 0.1, 0.0), 0.2),
+            (Point3::new(0.3, 0.1, 0.0), 0.2),
+            (Point3::new(1.0, 0.0, 0.0), 0.2),
+        ];
+        let curves = Curves::new(&[strand], CurveShape::Tube, &material).unwrap();
+
+        let ray = Ray::new(Point3::new(0.0, 0.075, 5.0), Vec3::new(0.0, 0.0, -1.0), 0.0);
+        let hit = curves.hit(ray, 0.0..f64::INFINITY).unwrap();
+        assert!((hit.u - 0.5).abs() < 0.01);
+        assert!((hit.t - 4.9).abs() < 0.01);
+        let tangent = hit.tangent.unwrap();
+        assert!((tangent.x().abs() - 1.0).abs() < 1e-3);
+
+        let miss = Ray::new(Point3::new(0.0, 0.3, 5.0), Vec3::new(0.0, 0.0, -1.0), 0.0);
+        assert!(curves.hit(miss, 0.0..f64::INFINITY).is_none());
+
+        // the strand doesn't move, so it has the same bounds at any time
+        let bbox = curves.bounding_box(0.0..1.0).unwrap();
+        assert_eq!(curves.bounding_box(0.5..0.5), Some(bbox.clone()));
+        assert!(bbox.min.x() <= -1.0 && bbox.max.x() >= 1.0);
+    }
+}
@@ -0,0 +1,84 @@
+use std::{ops::Range, sync::Arc};
+
+use crate::{
+    geom::{Aabb, Frame, Point3, Ray, Vec3},
+    material::Material,
+    polynomial::solve_quadratic,
+};
+
+use super::{
+    quadric::{azimuth, disk_hit, LocalHit},
+    HitRecord, Hittable,
+};
+
+/// A cylinder running from `base` to `top`, optionally closed at both ends.
+#[derive(Clone)]
+pub struct Cylinder {
+    frame: Frame,
+    height: f64,
+    radius: f64,
+    capped: bool,
+    material: Arc<dyn Material>,
+}
+
+impl Cylinder {
+    pub fn new(
+        base: Point3,
+        top: Point3,
+        radius: f64,
+        capped: bool,
+        material: Arc<dyn Material>,
+    ) -> Self {
+        Self {
+            frame: Frame::new(base, top - base),
+            height: (top - base).length(),
+            radius,
+            capped,
+            material,
+        }
+    }
+}
+
+impl Hittable for Cylinder {
+    fn hit(&self, ray: Ray, t_range: Range<f64>) -> Option<HitRecord> {
+        let local = self.frame.ray_to_local(ray);
+        let o = local.origin;
+        let d = local.direction;
+
+        let mut hits: Vec<LocalHit> = solve_quadratic(
+            d.x().powi(2) + d.y().powi(2),
+            2.0 * (o.x() * d.x() + o.y() * d.y()),
+            o.x().powi(2) + o.y().powi(2) - self.radius.powi(2),
+        )
+        .into_iter()
+        .filter_map(|t| {
+            let p = local.along(t);
+            (0.0..=self.height).contains(&p.z()).then(|| LocalHit {
+                t,
+                normal: Vec3::new(p.x(), p.y(), 0.0) / self.radius,
+                u: azimuth(p),
+                v: p.z() / self.height,
+            })
+        })
+        .collect();
+
+        if self.capped {
+            hits.extend(disk_hit(local, 0.0, self.radius, Vec3::new(0.0, 0.0, -1.0)));
+            hits.extend(disk_hit(
+                local,
+                self.height,
+                self.radius,
+                Vec3::new(0.0, 0.0, 1.0),
+            ));
+        }
+
+        LocalHit::closest(hits, t_range, ray, &self.frame, &self.material)
+    }
+
+    fn bounding_box(&self, _time_range: Range<f64>) -> Option<Aabb> {
+        self.frame.bounding_box(&Aabb::new(
+            Point3::new(-self.radius, -self.radius, 0.0),
+            Point3::new(self.radius, self.radius, self.height),
+        ))
+    }
+}
@@ -0,0 +1,301 @@
+use anyhow::Result;
+use std::{fs::File, io::BufReader, ops::Range, path::Path, sync::Arc};
+
+use crate::{
+    geom::{Aabb, Point3, Ray, Vec3},
+    material::Material,
+};
+
+use super::{HitRecord, Hittable};
+
+/// Terrain built from a grayscale image, where each pixel is the height of
+/// one vertex of a regular grid. Each grid cell is split into two triangles.
+#[derive(Clone)]
+pub struct Heightfield {
+    /// The corner of the footprint with the smallest X and Z, at zero height.
+    origin: Point3,
+    cell_size: (f64, f64),
+    /// The number of vertices along X and Z.
+    resolution: (usize, usize),
+    heights: Arc<[f64]>,
+    normals: Arc<[Vec3]>,
+    max_height: f64,
+    material: Arc<dyn Material>,
+}
+
+impl Heightfield {
+    /// Loads a heightfield whose footprint is centered on `center`, spanning
+    /// `size` along X and Z. Black pixels are at the height of `center` and
+    /// white ones are `height` above it.
+    pub fn new(
+        path: &Path,
+        center: Point3,
+        size: (f64, f64),
+        height: f64,
+        material: Arc<dyn Material>,
+    ) -> Result<Self> {
+        let f = File::open(path)?;
+        let f = BufReader::new(f);
+        let f = image::io::Reader::new(f).with_guessed_format()?;
+        Self::from_image(&f.decode()?, center, size, height, material)
+    }
+
+    /// Builds a heightfield from an image that is already loaded, like `new`.
+    pub fn from_image(
+        image: &image::DynamicImage,
+        center: Point3,
+        size: (f64, f64),
+        height: f64,
+        material: Arc<dyn Material>,
+    ) -> Result<Self> {
+        let image = image.to_luma16();
+        let (nx, nz) = (image.width() as usize, image.height() as usize);
+        anyhow::ensure!(nx >= 2 && nz >= 2, "heightfield image must be at least 2x2");
+        let heights: Arc<[f64]> = image
+            .pixels()
+            .map(|p| p.0[0] as f64 / u16::MAX as f64 * height)
+            .collect();
+
+        let cell_size = (size.0 / (nx - 1) as f64, size.1 / (nz - 1) as f64);
+        let mut heightfield = Self {
+            origin: center - Vec3::new(size.0 / 2.0, 0.0, size.1 / 2.0),
+            cell_size,
+            resolution: (nx, nz),
+            normals: Arc::new([]),
+            max_height: heights.iter().copied().fold(0.0, f64::max),
+            heights,
+            material,
+        };
+        heightfield.normals = itertools::iproduct!(0..nz, 0..nx)
+            .map(|(k, i)| heightfield.vertex_normal(i, k))
+            .collect();
+        Ok(heightfield)
+    }
+
+    fn height(&self, i: usize, k: usize) -> f64 {
+        self.heights[k * self.resolution.0 + i]
+    }
+
+    fn vertex(&self, i: usize, k: usize) -> Point3 {
+        self.origin
+            + Vec3::new(
+                i as f64 * self.cell_size.0,
+                self.height(i, k),
+                k as f64 * self.cell_size.1,
+            )
+    }
+
+    /// Estimates the surface normal at a vertex from the slope to its
+    /// neighbors, for smooth shading.
+    fn vertex_normal(&self, i: usize, k: usize) -> Vec3 {
+        let (nx, nz) = self.resolution;
+        let (i0, i1) = (i.saturating_sub(1), (i + 1).min(nx - 1));
+        let (k0, k1) = (k.saturating_sub(1), (k + 1).min(nz - 1));
+        let dx = (self.height(i1, k) - self.height(i0, k)) / ((i1 - i0) as f64 * self.cell_size.0);
+        let dz = (self.height(i, k1) - self.height(i, k0)) / ((k1 - k0) as f64 * self.cell_size.1);
+        Vec3::new(-dx, 1.0, -dz).unit_vector()
+    }
+
+    /// Intersects the two triangles that make up the cell whose lowest
+    /// corner is vertex `(i, k)`.
+    fn hit_cell(&self, ray: Ray, i: usize, k: usize, t_range: &Range<f64>) -> Option<HitRecord> {
+        let corners = [(i, k), (i + 1, k), (i + 1, k + 1), (i, k + 1)];
+        [[0, 2, 1], [0, 3, 2]]
+            .into_iter()
+            .filter_map(|triangle| {
+                let [a, b, c] = triangle.map(|idx| corners[idx]);
+                let (t, b1, b2) = intersect_triangle(
+                    ray,
+                    self.vertex(a.0, a.1),
+                    self.vertex(b.0, b.1),
+                    self.vertex(c.0, c.1),
+                )?;
+                t_range.contains(&t).then_some((t, a, b, c, b1, b2))
+            })
+            .min_by(|x, y| x.0.total_cmp(&y.0))
+            .map(|(t, a, b, c, b1, b2)| {
+                let b0 = 1.0 - b1 - b2;
+                let geometric_normal = (self.vertex(b.0, b.1) - self.vertex(a.0, a.1))
+                    .cross(self.vertex(c.0, c.1) - self.vertex(a.0, a.1));
+                let shading_normal = (b0 * self.normals[a.1 * self.resolution.0 + a.0]
+                    + b1 * self.normals[b.1 * self.resolution.0 + b.0]
+                    + b2 * self.normals[c.1 * self.resolution.0 + c.0])
+                    .unit_vector();
+
+                let p = ray.along(t);
+                let (nx, nz) = self.resolution;
+                let u = (p.x() - self.origin.x()) / (self.cell_size.0 * (nx - 1) as f64);
+                // image rows go down, but texture coordinates go up
+                let v = 1.0 - (p.z() - self.origin.z()) / (self.cell_size.1 * (nz - 1) as f64);
+
+                let mut record =
+                    HitRecord::new(p, t, ray, geometric_normal, self.material.clone(), u, v);
+                record.normal = if record.front_face {
+                    shading_normal
+                } else {
+                    -shading_normal
+                };
+                record
+            })
+    }
+}
+
+/// Möller-Trumbore ray-triangle intersection, returning the distance along
+/// the ray and the barycentric coordinates of `b` and `c`.
+fn intersect_triangle(ray: Ray, a: Point3, b: Point3, c: Point3) -> Option<(f64, f64, f64)> {
+    let edge1 = b - a;
+    let edge2 = c - a;
+    let p = ray.direction.cross(edge2);
+    let det = edge1.dot(p);
+    if det.abs() < 1e-12 {
+        return None;
+    }
+    let inv_det = det.recip();
+    let s = ray.origin - a;
+    let b1 = s.dot(p) * inv_det;
+    if !(0.0..=1.0).contains(&b1) {
+        return None;
+    }
+    let q = s.cross(edge1);
+    let b2 = ray.direction.dot(q) * inv_det;
+    if b2 < 0.0 || b1 + b2 > 1.0 {
+        return None;
+    }
+    Some((edge2.dot(q) * inv_det, b1, b2))
+}
+
+impl Hittable for Heightfield {
+    fn hit(&self, ray: Ray, t_range: Range<f64>) -> Option<HitRecord> {
+        let bounds = self.bounding_box(0.0..0.0)?;
+        let clipped = bounds.clip(ray, t_range.clone())?;
+
+        // Walk through the grid cells the ray passes over, in order, so the
+        // first cell with a hit has the closest one.
+        let cells = (self.resolution.0 - 1, self.resolution.1 - 1);
+        let start = ray.along(clipped.start) - self.origin;
+        let mut cell = [
+            ((start.x() / self.cell_size.0).floor().max(0.0) as usize).min(cells.0 - 1),
+            ((start.z() / self.cell_size.1).floor().max(0.0) as usize).min(cells.1 - 1),
+        ];
+
+        let per_axis = [
+            (
+                ray.direction.x(),
+                ray.origin.x() - self.origin.x(),
+                self.cell_size.0,
+            ),
+            (
+                ray.direction.z(),
+                ray.origin.z() - self.origin.z(),
+                self.cell_size.1,
+            ),
+        ];
+        let mut t_next = [0.0; 2];
+        let mut t_delta = [0.0; 2];
+        for (axis, &(dir, origin, size)) in per_axis.iter().enumerate() {
+            if dir == 0.0 {
+                t_next[axis] = f64::INFINITY;
+                t_delta[axis] = f64::INFINITY;
+            } else {
+                let boundary = if dir > 0.0 {
+                    cell[axis] + 1
+                } else {
+                    cell[axis]
+                };
+                t_next[axis] = (boundary as f64 * size - origin) / dir;
+                t_delta[axis] = size / dir.abs();
+            }
+        }
+
+        loop {
+            if let Some(hit) = self.hit_cell(ray, cell[0], cell[1], &t_range) {
+                return Some(hit);
+            }
+            let axis = usize::from(t_next[1] < t_next[0]);
+            if t_next[axis] > clipped.end {
+                return None;
+            }
+            let dir = per_axis[axis].0;
+            if dir > 0.0 {
+                cell[axis] += 1;
+                if cell[axis] >= [cells.0, cells.1][axis] {
+                    return None;
+                }
+            } else {
+                if cell[axis] == 0 {
+                    return None;
+                }
+                cell[axis] -= 1;
+            }
+            t_next[axis] += t_delta[axis];
+        }
+    }
+
+    fn bounding_box(&self, _time_range: Range<f64>) -> Option<Aabb> {
+        let (nx, nz) = self.resolution;
+        // pad the height so flat terrain still has a non-zero thickness
+        Some(Aabb::new(
+            self.origin - Vec3::new(0.0, 0.001, 0.0),
+            self.origin
+                + Vec3::new(
+                    self.cell_size.0 * (nx - 1) as f64,
+                    self.max_height + 0.001,
+                    self.cell_size.1 * (nz - 1) as f64,
+                ),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Heightfield;
+    use crate::{
+        geom::{Color, Point3, Ray, Vec3},
+        hittable::Hittable,
+        material::Lambertian,
+        texture::SolidColor,
+    };
+    use std::sync::Arc;
+
+    #[test]
+    fn test_hit() {
+        // a single white pixel in the middle makes a pyramid
+        let mut image = image::GrayImage::new(3, 3);
+        image.put_pixel(1, 1, image::Luma([255]));
+
+        let material = Arc::new(Lambertian {
+            albedo: Box::new(SolidColor(Color::white())),
+        });
+        let heightfield = Heightfield::from_image(
+            &image::DynamicImage::ImageLuma8(image),
+            Point3::default(),
+            (2.0, 2.0),
+            1.0,
+            material,
+        )
+        .unwrap();
+
+        let down = Vec3::new(0.0, -1.0, 0.0);
+        let peak = heightfield
+            .hit(Ray::new(Point3::new(0.0, 5.0, 0.0), down, 0.0), 0.001..f64::INFINITY)
+            .unwrap();
+        assert!((peak.p.y() - 1.0).abs() < 1e-9);
+        assert!(peak.front_face);
+
+        // halfway down the slope, coming in sideways
+        let side = heightfield
+            .hit(
+                Ray::new(Point3::new(-5.0, 0.5, 0.0), Vec3::new(1.0, 0.0, 0.0), 0.0),
+                0.001..f64::INFINITY,
+            )
+            .unwrap();
+        assert!((side.p.x() + 0.5).abs() < 1e-9);
+
+        let corner = heightfield
+            .hit(Ray::new(Point3::new(0.9, 5.0, 0.9), down, 0.0), 0.001..f64::INFINITY)
+            .unwrap();
+        assert!(corner.p.y().abs() < 0.2);
+        assert!((corner.u - 0.95).abs() < 1e-9);
+        assert!((corner.v - 0.05).abs() < 1e-9);
+    }
+}
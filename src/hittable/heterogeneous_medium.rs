@@ -0,0 +1,225 @@
+use rand::Rng;
+use std::{ops::Range, sync::Arc};
+
+use crate::{
+    geom::{Aabb, Color, Ray, Vec3},
+    material::Material,
+    texture::{Texture, Volume},
+};
+
+use super::{constant_medium::spans_inside, HitRecord, Hittable};
+
+/// A participating medium whose density changes from place to place, like
+/// smoke or clouds.
+///
+/// Scattering distances are found with delta tracking: the medium is treated
+/// as if it were filled to its maximum density (the majorant) everywhere,
+/// and each tentative collision is kept with probability equal to the real
+/// density over the majorant. The rest are null collisions that the ray
+/// passes straight through. This gives unbiased transmittance without
+/// stepping through the volume in fixed increments.
+///
+/// Glowing media do step through it to add up their emission, which the
+/// integrator asks for along each stretch of ray between collisions.
+#[derive(Clone)]
+pub struct HeterogeneousMedium {
+    boundary: Box<dyn Hittable>,
+    volume: Box<dyn Volume>,
+    majorant: f64,
+    phase_function: Arc<dyn Material>,
+    glow: Option<Glow>,
+}
+
+/// The most points the emission is sampled at along a stretch of ray inside
+/// a glowing medium. Otherwise there is one for each mean free path at the
+/// majorant, which is about as far as the density can change much.
+const MAX_EMISSION_STEPS: f64 = 64.0;
+
+impl HeterogeneousMedium {
+    pub fn new(
+        boundary: Box<dyn Hittable>,
+        volume: Box<dyn Volume>,
+        phase_function: Arc<dyn Material>,
+    ) -> Self {
+        Self {
+            boundary,
+            majorant: volume.max_density(),
+            volume,
+            phase_function,
+            glow: None,
+        }
+    }
+
+    /// Makes the medium glow, like fire or a nebula. `albedo` should be the
+    /// color the phase function scatters light with, since only the light
+    /// the medium absorbs, rather than scatters, is given back off as a glow.
+    #[must_use]
+    pub fn with_emission(self, emission: Emission, albedo: Box<dyn Texture>) -> Self {
+        Self {
+            glow: Some(Glow { albedo, emission }),
+            ..self
+        }
+    }
+}
+
+/// Light given off by a medium. `volume` is the radiance of the glowing
+/// matter, and `color` tints it. Where the medium is thick enough to be
+/// opaque, it shines with exactly this radiance.
+#[derive(Clone)]
+pub struct Emission {
+    pub volume: Box<dyn Volume>,
+    pub color: Color,
+}
+
+/// What makes a medium glow. It emits in proportion to how much light it
+/// absorbs, as hot gas does, so the emission along a ray is the density
+/// times the absorbed fraction, `1 - albedo`, times the radiance.
+#[derive(Clone)]
+struct Glow {
+    albedo: Box<dyn Texture>,
+    emission: Emission,
+}
+
+impl Hittable for HeterogeneousMedium {
+    fn hit(&self, ray: Ray, t_range: Range<f64>) -> Option<HitRecord> {
+        if self.majorant <= 0.0 {
+            return None;
+        }
+
+        let mut rng = rand::thread_rng();
+        let majorant_per_t = self.majorant * ray.direction.length();
+        for span in spans_inside(self.boundary.as_ref(), ray, t_range) {
+            let mut t = span.start;
+            loop {
+                t -= (1.0 - rng.gen::<f64>()).ln() / majorant_per_t;
+                if t >= span.end {
+                    break;
+                }
+
+                let p = ray.along(t);
+                if rng.gen::<f64>() * self.majorant < self.volume.density(p) {
+                    return Some(HitRecord::new(
+                        p,
+                        t,
+                        ray,
+                        Vec3::new(1.0, 0.0, 0.0),
+                        self.phase_function.clone(),
+                        0.0,
+                        0.0,
+                    ));
+                }
+            }
+        }
+        None
+    }
+
+    fn emission(&self, ray: Ray, t_range: Range<f64>) -> Color {
+        let Some(glow) = &self.glow else {
+            return Color::black();
+        };
+
+        // the points are evenly spaced from a random start, so their sum is
+        // unbiased
+        let mut rng = rand::thread_rng();
+        let majorant_per_t = self.majorant * ray.direction.length();
+        let mut emission = Color::black();
+        for span in spans_inside(self.boundary.as_ref(), ray, t_range) {
+            let steps = ((span.end - span.start) * majorant_per_t)
+                .ceil()
+                .clamp(1.0, MAX_EMISSION_STEPS) as u32;
+            let step = (span.end - span.start) / f64::from(steps);
+            let offset = rng.gen::<f64>();
+            let mut sum = Color::black();
+            for i in 0..steps {
+                let p = ray.along(span.start + (f64::from(i) + offset) * step);
+                let absorbed = Color::white() - glow.albedo.value(0.0, 0.0, p);
+                sum += absorbed
+                    * glow.emission.color
+                    * (self.volume.density(p) * glow.emission.volume.density(p));
+            }
+            emission += sum * (step * ray.direction.length());
+        }
+        emission
+    }
+
+    fn bounding_box(&self, time_range: Range<f64>) -> Option<Aabb> {
+        self.boundary.bounding_box(time_range)
+    }
+
+    fn refit(&mut self, time_range: Range<f64>) {
+        self.boundary.refit(time_range);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Emission, HeterogeneousMedium};
+    use crate::{
+        geom::{Color, Point3, Ray, Vec3},
+        hittable::{Hittable, Sphere},
+        material::Lambertian,
+        texture::{Isotropic, SolidColor, Volume},
+    };
+    use std::sync::Arc;
+
+    #[derive(Clone)]
+    struct Constant(f64);
+
+    impl Volume for Constant {
+        fn density(&self, _p: Point3) -> f64 {
+            self.0
+        }
+
+        fn max_density(&self) -> f64 {
+            self.0
+        }
+    }
+
+    #[test]
+    fn test_emission() {
+        let boundary = Sphere {
+            center: Point3::default(),
+            radius: 1.0,
+            material: Arc::new(Lambertian {
+                albedo: Box::new(SolidColor(Color::white())),
+            }),
+        };
+        let black = || Box::new(SolidColor(Color::black()));
+        let medium = HeterogeneousMedium::new(
+            Box::new(boundary),
+            Box::new(Constant(0.5)),
+            Arc::new(Isotropic { albedo: black() }),
+        )
+        .with_emission(
+            Emission {
+                volume: Box::new(Constant(2.0)),
+                color: Color::new(1.0, 0.5, 0.25),
+            },
+            black(),
+        );
+
+        // straight through the middle of a black, glowing sphere. Without
+        // stopping at collisions, it absorbs at a rate of 0.5 over a length
+        // of 2, and so gives off exactly its radiance.
+        let ray = Ray::new(Point3::new(0.0, 0.0, -5.0), Vec3::new(0.0, 0.0, 1.0), 0.0);
+        let radiance = Color::new(2.0, 1.0, 0.5);
+        let unblocked = medium.emission(ray, 0.0..f64::INFINITY);
+        assert!((unblocked - radiance).length() < 1e-9);
+
+        // stopping at the first collision, as the integrator does, leaves the
+        // glow attenuated by the medium in front of it. The sphere lets
+        // through e^-1 of the light behind it, and so gives off 1 - e^-1 of
+        // its own radiance.
+        let samples = 20_000;
+        let total = (0..samples)
+            .map(|_| {
+                let t_max = medium
+                    .hit(ray, 0.0..f64::INFINITY)
+                    .map_or(f64::INFINITY, |hit| hit.t);
+                medium.emission(ray, 0.0..t_max)
+            })
+            .fold(Color::black(), |a, b| a + b);
+        let expected = (1.0 - (-1.0_f64).exp()) * radiance;
+        assert!((total / f64::from(samples) - expected).length() < 0.05);
+    }
+}
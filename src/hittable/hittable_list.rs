@@ -1,6 +1,9 @@
 use std::ops::Range;
 
-use crate::{geom::{Aabb, Ray}, hittable::Hittable};
+use crate::{
+    geom::{Aabb, Color, Ray},
+    hittable::{Csg, CsgOp, Hittable, Interval},
+};
 use ordered_float::OrderedFloat;
 
 #[derive(Clone, Default)]
@@ -31,6 +34,19 @@ impl Hittable for HittableList {
             .min_by_key(|hr| OrderedFloat(hr.t))
     }
 
+    fn occluded(&self, ray: Ray, t_range: Range<f64>) -> bool {
+        self.objects
+            .iter()
+            .any(|h| h.occluded(ray, t_range.clone()))
+    }
+
+    fn emission(&self, ray: Ray, t_range: Range<f64>) -> Color {
+        self.objects
+            .iter()
+            .map(|h| h.emission(ray, t_range.clone()))
+            .fold(Color::black(), |a, b| a + b)
+    }
+
     fn bounding_box(&self, time_range: Range<f64>) -> Option<Aabb> {
         // If any of the objects has an undefined bounding box, propogate that.
         let bounding_boxes: Option<Vec<_>> = self
@@ -40,6 +56,18 @@ impl Hittable for HittableList {
             .collect();
         bounding_boxes.and_then(|bbs| Aabb::surrounding(&(bbs.iter().collect::<Vec<_>>())))
     }
+
+    fn refit(&mut self, time_range: Range<f64>) {
+        for object in &mut self.objects {
+            object.refit(time_range.clone());
+        }
+    }
+
+    fn intervals(&self, ray: Ray) -> Vec<Interval> {
+        self.objects.iter().fold(vec![], |acc, object| {
+            Csg::combine(CsgOp::Union, acc, object.intervals(ray))
+        })
+    }
 }
 
 impl<H> FromIterator<H> for HittableList
@@ -1,103 +1,333 @@
-use super::{HitRecord, Hittable};
-use crate::geom::{Aabb, Point3, PointCloud, Ray, Vec3};
-use std::ops::Range;
+use super::{rounding_error, HitRecord, Hittable, Interval};
+use crate::geom::{Aabb, Color, Pose, Ray, Transform, Vec3};
+use std::{ops::Range, sync::Arc};
 
+/// A placement of an object that may be shared between many instances, so
+/// its geometry is only stored once.
 #[derive(Clone)]
-pub struct Translate {
-    pub hittable: Box<dyn Hittable>,
-    pub offset: Vec3,
+pub struct Instance {
+    pub object: Arc<dyn Hittable>,
+    pub transform: Transform,
 }
 
-impl Hittable for Translate {
-    fn hit(&self, ray: Ray, t_range: Range<f64>) -> Option<super::HitRecord> {
-        let moved = Ray::new(ray.origin - self.offset, ray.direction, ray.time);
-        self.hittable.hit(moved, t_range).map(|mut hit_record| {
-            hit_record.p += self.offset;
-            hit_record.set_face_normal(moved, hit_record.normal);
-            hit_record
+fn record_to_world(transform: &Transform, hit_record: HitRecord) -> HitRecord {
+    // the transformed normal keeps its angle to the transformed ray, so the
+    // face is still right
+    // the error is carried over at the scale of the transform, along with
+    // that of transforming the point itself
+    let p = transform.point(hit_record.p);
+    let error = hit_record.error;
+    HitRecord {
+        p,
+        normal: transform.normal(hit_record.normal),
+        tangent: hit_record
+            .tangent
+            .map(|t| transform.vector(t).unit_vector()),
+        geometric_normal: transform.normal(hit_record.geometric_normal),
+        error: transform.vector(Vec3::new(error, error, error)).length() + rounding_error(p),
+        ..hit_record
+    }
+}
+
+fn intervals_to_world(transform: &Transform, intervals: Vec<Interval>) -> Vec<Interval> {
+    intervals
+        .into_iter()
+        .map(|Interval { enter, exit }| Interval {
+            enter: record_to_world(transform, enter),
+            exit: record_to_world(transform, exit),
         })
+        .collect()
+}
+
+impl Hittable for Instance {
+    fn hit(&self, ray: Ray, t_range: Range<f64>) -> Option<HitRecord> {
+        self.object
+            .hit(self.transform.inverse_ray(ray), t_range)
+            .map(|hit_record| record_to_world(&self.transform, hit_record))
+    }
+
+    fn occluded(&self, ray: Ray, t_range: Range<f64>) -> bool {
+        self.object
+            .occluded(self.transform.inverse_ray(ray), t_range)
     }
 
-    fn bounding_box(&self, time_range: Range<f64>) -> Option<crate::geom::Aabb> {
-        self.hittable
+    fn emission(&self, ray: Ray, t_range: Range<f64>) -> Color {
+        self.object
+            .emission(self.transform.inverse_ray(ray), t_range)
+    }
+
+    fn bounding_box(&self, time_range: Range<f64>) -> Option<Aabb> {
+        self.object
             .bounding_box(time_range)
-            .map(|bb| Aabb::new(bb.min + self.offset, bb.max + self.offset))
+            .and_then(|bbox| self.transform.bounding_box(&bbox))
+    }
+
+    fn refit(&mut self, time_range: Range<f64>) {
+        // objects shared between instances keep the bounds they were built
+        // with, which still cover any time inside those they were built for
+        if let Some(object) = Arc::get_mut(&mut self.object) {
+            object.refit(time_range);
+        }
+    }
+
+    fn intervals(&self, ray: Ray) -> Vec<Interval> {
+        let intervals = self.object.intervals(self.transform.inverse_ray(ray));
+        intervals_to_world(&self.transform, intervals)
     }
 }
 
+/// How many poses to check, at least, when finding the bounding box of an
+/// animated object over a time range.
+const BOUNDING_SAMPLES: usize = 64;
+
+/// An object that moves, turns and changes size over time, following a set of
+/// keyframes. Rays see the object as it was at the ray's time.
 #[derive(Clone)]
-pub struct RotateY {
-    hittable: Box<dyn Hittable>,
-    sin_theta: f64,
-    cos_theta: f64,
-    zero_bbox: Option<Aabb>,
+pub struct Animated {
+    object: Arc<dyn Hittable>,
+    keyframes: Vec<(f64, Pose)>,
+    /// The transform of each keyframe, for rays at or outside them.
+    transforms: Vec<Transform>,
 }
 
-impl RotateY {
-    pub fn new(hittable: Box<dyn Hittable>, theta: f64) -> Self {
-        let (sin_theta, cos_theta) = theta.sin_cos();
-        let mut rv = Self {
-            hittable,
-            sin_theta,
-            cos_theta,
-            zero_bbox: None,
-        };
-        rv.zero_bbox = rv.bounding_box(0.0..0.0);
-        rv
+impl Animated {
+    pub fn new(object: Arc<dyn Hittable>, mut keyframes: Vec<(f64, Pose)>) -> Self {
+        keyframes.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let transforms = keyframes.iter().map(|(_, pose)| pose.transform()).collect();
+        Self {
+            object,
+            keyframes,
+            transforms,
+        }
+    }
+
+    /// The transform of `pose_at(time)`, which only needs working out
+    /// between keyframes.
+    fn transform_at(&self, time: f64) -> Transform {
+        let next = self.keyframes.partition_point(|(t, _)| *t <= time);
+        // the object holds still before its first keyframe and after its
+        // last, and is exactly in a keyframe's pose at its time
+        if next == 0 || next == self.keyframes.len() || self.keyframes[next - 1].0 >= time {
+            let held = self.transforms.get(next.saturating_sub(1));
+            return held.cloned().unwrap_or_default();
+        }
+        self.pose_at(time).transform()
+    }
+
+    /// Interpolates between the keyframes on either side of `time`, holding
+    /// still before the first keyframe and after the last.
+    pub fn pose_at(&self, time: f64) -> Pose {
+        let next = self.keyframes.partition_point(|(t, _)| *t <= time);
+        match (
+            next.checked_sub(1).map(|i| &self.keyframes[i]),
+            self.keyframes.get(next),
+        ) {
+            (Some((t0, a)), Some((t1, b))) => a.lerp(b, (time - t0) / (t1 - t0)),
+            (Some((_, pose)), None) | (None, Some((_, pose))) => pose.clone(),
+            (None, None) => Pose::default(),
+        }
     }
 }
 
-impl Hittable for RotateY {
-    fn hit(&self, ray: Ray, t_range: Range<f64>) -> Option<super::HitRecord> {
-        let mut origin = ray.origin;
-        let mut direction = ray.direction;
+impl Hittable for Animated {
+    fn hit(&self, ray: Ray, t_range: Range<f64>) -> Option<HitRecord> {
+        let transform = self.transform_at(ray.time);
+        self.object
+            .hit(transform.inverse_ray(ray), t_range)
+            .map(|hit_record| record_to_world(&transform, hit_record))
+    }
+
+    fn occluded(&self, ray: Ray, t_range: Range<f64>) -> bool {
+        let transform = self.transform_at(ray.time);
+        self.object.occluded(transform.inverse_ray(ray), t_range)
+    }
+
+    fn emission(&self, ray: Ray, t_range: Range<f64>) -> Color {
+        let transform = self.transform_at(ray.time);
+        self.object.emission(transform.inverse_ray(ray), t_range)
+    }
+
+    fn refit(&mut self, time_range: Range<f64>) {
+        if let Some(object) = Arc::get_mut(&mut self.object) {
+            object.refit(time_range);
+        }
+    }
+
+    fn bounding_box(&self, time_range: Range<f64>) -> Option<Aabb> {
+        let object_bbox = self.object.bounding_box(time_range.clone())?;
+
+        // Sample the pose evenly over the time range, and at every keyframe
+        // in it, since those are where the motion changes direction.
+        let mut times: Vec<f64> = (0..=BOUNDING_SAMPLES)
+            .map(|i| {
+                let t = i as f64 / BOUNDING_SAMPLES as f64;
+                time_range.start + t * (time_range.end - time_range.start)
+            })
+            .chain(
+                self.keyframes
+                    .iter()
+                    .map(|(t, _)| *t)
+                    .filter(|t| time_range.contains(t)),
+            )
+            .collect();
+        times.sort_by(f64::total_cmp);
+        let poses: Vec<Pose> = times.iter().map(|t| self.pose_at(*t)).collect();
+
+        let boxes = poses
+            .iter()
+            .map(|pose| pose.transform().bounding_box(&object_bbox))
+            .collect::<Option<Vec<_>>>()?;
+        let bbox = Aabb::surrounding(&boxes.iter().collect::<Vec<_>>())?;
+
+        // Between samples, corners move along arcs rather than straight
+        // lines. Pad the box by how far an arc can bulge past its chord.
+        let max_turn = poses
+            .windows(2)
+            .map(|pair| {
+                let turn = pair[1].rotation - pair[0].rotation;
+                turn.x().abs() + turn.y().abs() + turn.z().abs()
+            })
+            .fold(0.0, f64::max);
+        let radius = poses
+            .iter()
+            .flat_map(|pose| {
+                object_bbox
+                    .corners()
+                    .into_iter()
+                    .map(|c| (pose.scale * c).length())
+            })
+            .fold(0.0, f64::max);
+        let pad = radius * max_turn.powi(2) / 8.0;
+        let pad = Vec3::new(pad, pad, pad);
+        Some(Aabb::new(bbox.min - pad, bbox.max + pad))
+    }
 
-        origin[0] = self.cos_theta * ray.origin.x() - self.sin_theta * ray.origin.z();
-        origin[2] = self.sin_theta * ray.origin.x() + self.cos_theta * ray.origin.z();
+    fn intervals(&self, ray: Ray) -> Vec<Interval> {
+        let transform = self.transform_at(ray.time);
+        let intervals = self.object.intervals(transform.inverse_ray(ray));
+        intervals_to_world(&transform, intervals)
+    }
+}
 
-        direction[0] = self.cos_theta * ray.direction.x() - self.sin_theta * ray.direction.z();
-        direction[2] = self.sin_theta * ray.direction.x() + self.cos_theta * ray.direction.z();
+#[cfg(test)]
+mod tests {
+    use super::{Animated, Instance};
+    use crate::{
+        geom::{Color, Point3, Pose, Ray, Transform, Vec3},
+        hittable::{Capsule, Cuboid, Cylinder, Hittable, Sphere},
+        material::{Lambertian, Material},
+        texture::SolidColor,
+    };
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+    use std::sync::Arc;
 
-        let rotated = Ray::new(origin, direction, ray.time);
+    #[test]
+    fn test_animated() {
+        let material: Arc<dyn Material> = Arc::new(Lambertian {
+            albedo: Box::new(SolidColor(Color::white())),
+        });
+        let cube = Cuboid::new(Point3::default(), Vec3::new(1.0, 1.0, 1.0), &material);
+        let end = Pose {
+            offset: Vec3::new(10.0, 0.0, 0.0),
+            rotation: Vec3::new(0.0, 90_f64.to_radians(), 0.0),
+            ..Pose::default()
+        };
+        let animated = Animated::new(Arc::new(cube), vec![(1.0, end), (0.0, Pose::default())]);
 
-        self.hittable.hit(rotated, t_range).map(|hit_record| {
-            let mut p = hit_record.p;
-            let mut normal = hit_record.normal;
+        assert_eq!(animated.pose_at(0.5).offset, Vec3::new(5.0, 0.0, 0.0));
+        assert_eq!(animated.pose_at(2.0).offset, Vec3::new(10.0, 0.0, 0.0));
+        // asking twice, the second time from the cache, gives the same
+        // transform as working it out from scratch
+        for time in [-1.0, 0.0, 0.3, 0.3, 1.0, 2.0] {
+            assert_eq!(
+                animated.transform_at(time),
+                animated.pose_at(time).transform()
+            );
+        }
 
-            p[0] = self.cos_theta * hit_record.p.x() + self.sin_theta * hit_record.p.x();
-            p[2] = -self.sin_theta * hit_record.p.x() + self.cos_theta * hit_record.p.x();
+        // a ray at the middle of the shutter sees the cube halfway along
+        let down = Ray::new(Point3::new(5.0, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0), 0.5);
+        let hit = animated.hit(down, 0.001..f64::INFINITY).unwrap();
+        assert!((hit.p.y() - 0.5).abs() < 1e-9);
 
-            normal[0] =
-                self.cos_theta * hit_record.normal.x() + self.sin_theta * hit_record.normal.x();
-            normal[2] =
-                -self.sin_theta * hit_record.normal.x() + self.cos_theta * hit_record.normal.x();
+        // while spinning 45 degrees, the corners stick out further than the
+        // box does at either end of the motion
+        let bbox = animated.bounding_box(0.0..1.0).unwrap();
+        let reach = 0.5 * 2_f64.sqrt();
+        assert!(bbox.min.z() <= -reach && bbox.max.z() >= reach);
+        assert!(bbox.min.x() <= -0.5 && bbox.max.x() >= 10.5);
+    }
+
+    #[test]
+    fn test_spawn_ray() {
+        let material: Arc<dyn Material> = Arc::new(Lambertian {
+            albedo: Box::new(SolidColor(Color::white())),
+        });
+        let sphere = Arc::new(Sphere {
+            center: Point3::default(),
+            radius: 1.0,
+            material,
+        });
+        let mut rng = StdRng::seed_from_u64(3);
 
-            let mut rec = HitRecord {
-                p,
-                normal,
-                ..hit_record
+        // rays leaving the surface must neither hit it again where they left,
+        // nor miss it where they should pass through it, at any scale
+        for scale in [1e-6, 1.0, 1e6] {
+            let transform = Transform::scale(Vec3::new(scale, scale, scale))
+                .then(&Transform::translate(Vec3::new(1e3, -2e3, 5e2) * scale));
+            let instance = Instance {
+                object: sphere.clone(),
+                transform: transform.clone(),
             };
-            rec.set_face_normal(rotated, normal);
-            rec
-        })
+            for _ in 0..1000 {
+                let aim = Vec3::new(rng.gen_range(-0.7..0.7), rng.gen_range(-0.7..0.7), 0.0);
+                let ray = Ray::new(
+                    transform.point(Point3::new(0.0, 0.0, -5.0)),
+                    transform.vector(aim + Vec3::new(0.0, 0.0, 5.0)),
+                    0.0,
+                );
+                let hit = instance.hit(ray, 0.0..f64::INFINITY).unwrap();
+
+                let reflected = hit.spawn_ray(ray.direction.reflect(hit.normal), 0.0);
+                assert!(instance.hit(reflected, 0.0..f64::INFINITY).is_none());
+                assert!(!instance.occluded(reflected, 0.0..f64::INFINITY));
+
+                let through = hit.spawn_ray(ray.direction, 0.0);
+                let exit = instance.hit(through, 0.0..f64::INFINITY).unwrap();
+                assert!(!exit.front_face);
+                assert!((exit.p - hit.p).length() > 0.1 * scale);
+            }
+        }
     }
 
-    fn bounding_box(&self, time_range: Range<f64>) -> Option<Aabb> {
-        match (&self.zero_bbox, &time_range) {
-            (Some(bbox), tr) if *tr == (0.0..0.0) => Some(bbox.clone()),
-            _ => self.hittable.bounding_box(time_range).and_then(|bbox| {
-                bbox.corners()
-                    .iter()
-                    .map(|c| {
-                        Point3::new(
-                            self.cos_theta * c.x() + self.sin_theta * c.z(),
-                            c.y(),
-                            -self.sin_theta * c.x() + self.cos_theta * c.z(),
-                        )
-                    })
-                    .collect::<PointCloud>()
-                    .bounding_box()
-            }),
+    #[test]
+    fn test_scaled_quadrics() {
+        let material: Arc<dyn Material> = Arc::new(Lambertian {
+            albedo: Box::new(SolidColor(Color::white())),
+        });
+        let base = Point3::new(0.0, 0.0, -1.0);
+        let top = Point3::new(0.0, 0.0, 1.0);
+        let shapes: [Arc<dyn Hittable>; 2] = [
+            Arc::new(Cylinder::new(base, top, 1.0, true, material.clone())),
+            Arc::new(Capsule::new(base, top, 1.0, material)),
+        ];
+
+        // scaling up shrinks the direction in the shapes' own space, which
+        // must not make their quadratics look linear
+        for scale in [1.0, 1e3, 1e5, 1e6] {
+            for shape in &shapes {
+                let instance = Instance {
+                    object: shape.clone(),
+                    transform: Transform::scale(Vec3::new(scale, scale, scale)),
+                };
+                let ray = Ray::new(
+                    Point3::new(-5.0 * scale, 0.0, 0.0),
+                    Vec3::new(1.0, 0.0, 0.0),
+                    0.0,
+                );
+                let hit = instance.hit(ray, 0.0..f64::INFINITY).unwrap();
+                assert!((hit.t / scale - 4.0).abs() < 1e-6, "at scale {scale}");
+            }
         }
     }
 }
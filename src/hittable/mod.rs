@@ -6,29 +6,148 @@ mod sphere;
 mod cuboid;
 mod instance;
 mod constant_medium;
+mod heterogeneous_medium;
+mod quadric;
+mod cylinder;
+mod cone;
+mod torus;
+mod capsule;
+mod csg;
+mod sdf;
+mod heightfield;
+mod curves;
+mod sphere_set;
 
-pub use bvh::BvhNode;
+pub use bvh::{BvhBuilder, BvhNode, BvhOptions};
 pub use hittable_list::HittableList;
 pub use moving_sphere::MovingSphere;
 pub use rect::AxisAlignedRect;
 pub use sphere::Sphere;
 pub use cuboid::Cuboid;
-pub use instance::{Translate, RotateY};
+pub use instance::{Animated, Instance};
 pub use constant_medium::ConstantMedium;
+pub use heterogeneous_medium::{Emission, HeterogeneousMedium};
+pub use cylinder::Cylinder;
+pub use cone::Cone;
+pub use torus::Torus;
+pub use capsule::Capsule;
+pub use csg::{Csg, CsgOp};
+pub use sdf::{Sdf, SdfNode};
+pub use heightfield::Heightfield;
+pub use curves::{CurveShape, Curves};
+pub use sphere_set::SphereSet;
 
 use crate::{
-    geom::{Aabb, Point3, Ray, Vec3},
+    geom::{Aabb, Color, Point3, Ray, Vec3},
     material::Material,
 };
 use dyn_clonable::clonable;
 use std::{ops::Range, sync::Arc};
 
+/// The most hits the default `Hittable::intervals` follows along a ray,
+/// which only very complex objects, or very unlucky rays, would reach.
+const MAX_INTERVAL_HITS: usize = 1024;
+
+/// How far a hit point can stray from the true surface, relative to the size
+/// of the coordinates it was found from. This is far above the rounding error
+/// of a single operation, because tests like the quadratic for spheres lose
+/// precision to cancellation.
+const RELATIVE_ERROR: f64 = 1e-9;
+
+/// A bound on the floating point error in a point computed from coordinates
+/// around as large as `p`'s.
+pub(super) fn rounding_error(p: Point3) -> f64 {
+    RELATIVE_ERROR * p.max_abs()
+}
+
 #[clonable]
 pub trait Hittable: Send + Sync + Clone {
     fn hit(&self, ray: Ray, t_range: Range<f64>) -> Option<HitRecord>;
     fn bounding_box(&self, time_range: Range<f64>) -> Option<Aabb>;
+
+    /// Whether anything blocks the ray within `t_range`. Shadow and occlusion
+    /// rays only need to know this, so objects can skip finding the closest
+    /// hit and building a record of it.
+    fn occluded(&self, ray: Ray, t_range: Range<f64>) -> bool {
+        self.hit(ray, t_range).is_some()
+    }
+
+    /// Updates any bounds cached for hitting the object at other times, such
+    /// as a BVH's, for times in `time_range`. Objects holding others pass it
+    /// on to them.
+    fn refit(&mut self, _time_range: Range<f64>) {}
+
+    /// The light given off along `ray` within `t_range` by glowing media,
+    /// integrated over the segment. Nothing in front of each point dims it;
+    /// the integrator stops the segment at the first collision or surface,
+    /// and so the chance of reaching each point stands in for the
+    /// transmittance up to it.
+    fn emission(&self, _ray: Ray, _t_range: Range<f64>) -> Color {
+        Color::black()
+    }
+
+    /// Finds every stretch of the (infinite) line through `ray` that lies
+    /// inside this object, in order. This is only meaningful for closed
+    /// objects.
+    ///
+    /// The default implementation walks along the ray one hit at a time,
+    /// counting entries and exits, so overlapping parts are merged. Each
+    /// step starts from a ray spawned past the last hit, so it can't find
+    /// the same hit again at any scale, and its hits are moved back onto
+    /// `ray`'s `t`.
+    fn intervals(&self, ray: Ray) -> Vec<Interval> {
+        let mut intervals = vec![];
+        let mut depth = 0_usize;
+        let mut enter = None;
+        let mut next = self.hit(ray, -f64::INFINITY..f64::INFINITY);
+
+        for _ in 0..MAX_INTERVAL_HITS {
+            let Some(hit) = next else {
+                break;
+            };
+            let step = hit.spawn_ray(ray.direction, ray.time);
+            let t_base =
+                (step.origin - ray.origin).dot(ray.direction) / ray.direction.length_squared();
+            next = self.hit(step, 0.0..f64::INFINITY).map(|mut hit| {
+                hit.t += t_base;
+                hit
+            });
+            // A hit without any error, like one at the origin on a ray from
+            // the origin, spawns a ray right on the surface, which can find
+            // the same hit again. Then the walk moves on along `ray` itself,
+            // so that t always increases.
+            if next.as_ref().is_some_and(|next| next.t <= hit.t) {
+                next = self.hit(ray, hit.t.next_up()..f64::INFINITY);
+            }
+
+            if hit.front_face {
+                depth += 1;
+                if depth == 1 {
+                    enter = Some(hit);
+                }
+            } else if depth > 0 {
+                depth -= 1;
+                if depth == 0 {
+                    if let Some(enter) = enter.take() {
+                        intervals.push(Interval { enter, exit: hit });
+                    }
+                }
+            }
+        }
+
+        intervals
+    }
+}
+
+/// A stretch of a ray that is inside an object, bounded by the hits where the
+/// ray enters and exits.
+#[derive(Clone)]
+pub struct Interval {
+    pub enter: HitRecord,
+    pub exit: HitRecord,
 }
 
+#[derive(Clone)]
 pub struct HitRecord {
     pub p: Point3,
     pub normal: Vec3,
@@ -37,6 +156,15 @@ pub struct HitRecord {
     pub front_face: bool,
     pub u: f64,
     pub v: f64,
+    /// The direction along thin objects like curves, for materials that
+    /// scatter light around it.
+    pub tangent: Option<Vec3>,
+    /// The normal of the actual geometry, on the same side as `normal`, which
+    /// may have been smoothed for shading.
+    pub geometric_normal: Vec3,
+    /// How far `p` may be from the true surface, because of floating point
+    /// error.
+    pub error: f64,
 }
 
 impl HitRecord {
@@ -57,6 +185,9 @@ impl HitRecord {
             front_face: false,
             u,
             v,
+            tangent: None,
+            geometric_normal: Vec3::default(),
+            error: rounding_error(ray.origin) + rounding_error(p),
         };
         record.set_face_normal(ray, outward_normal);
         record
@@ -64,6 +195,56 @@ impl HitRecord {
 
     pub fn set_face_normal(&mut self, ray: Ray, outward_normal: Vec3) {
         self.front_face = ray.direction.dot(outward_normal) < 0.0;
-        self.normal = if self.front_face { outward_normal } else {-outward_normal}
+        self.normal = if self.front_face { outward_normal } else {-outward_normal};
+        self.geometric_normal = self.normal;
+    }
+
+    /// Starts a new ray, like a scattered or shadow ray, from the hit point.
+    /// The origin is pushed off the surface along the geometric normal, on
+    /// the side the ray leaves by, just far enough to clear the error in `p`.
+    /// That way the ray can't hit the surface it is leaving, whatever the
+    /// scale of the scene, and can be traced from `t = 0`.
+    pub fn spawn_ray(&self, direction: Vec3, time: f64) -> Ray {
+        let normal = self.geometric_normal.unit_vector();
+        // the furthest the error can reach along the normal
+        let distance = self.error * (normal.x().abs() + normal.y().abs() + normal.z().abs());
+        let offset = if direction.dot(normal) < 0.0 {
+            -distance * normal
+        } else {
+            distance * normal
+        };
+        Ray::new(self.p + offset, direction, time)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Cylinder, Hittable};
+    use crate::{
+        geom::{Color, Point3, Ray, Vec3},
+        material::{Lambertian, Material},
+        texture::SolidColor,
+    };
+    use std::sync::Arc;
+
+    #[test]
+    fn test_intervals_from_origin() {
+        let material: Arc<dyn Material> = Arc::new(Lambertian {
+            albedo: Box::new(SolidColor(Color::white())),
+        });
+        // a cylinder whose side passes through the origin, where hits have no
+        // rounding error to spawn the next step past
+        let cylinder = Cylinder::new(
+            Point3::new(1.0, 0.0, -1.0),
+            Point3::new(1.0, 0.0, 1.0),
+            1.0,
+            true,
+            material,
+        );
+        let ray = Ray::new(Point3::default(), Vec3::new(1.0, 0.0, 0.0), 0.0);
+        let intervals = cylinder.intervals(ray);
+        assert_eq!(intervals.len(), 1);
+        assert!(intervals[0].enter.t.abs() < 1e-9);
+        assert!((intervals[0].exit.t - 2.0).abs() < 1e-9);
     }
 }
@@ -34,6 +34,11 @@ impl Hittable for MovingSphere {
         fixed.hit(ray, t_range)
     }
 
+    fn occluded(&self, ray: Ray, t_range: Range<f64>) -> bool {
+        Sphere::roots(self.center_at(ray.time), self.radius, ray)
+            .is_some_and(|(near, far)| t_range.contains(&near) || t_range.contains(&far))
+    }
+
     fn bounding_box(&self, time_range: Range<f64>) -> Option<Aabb> {
         let box_start = Aabb::new(
             self.center_at(time_range.start) - Vec3::new(self.radius, self.radius, self.radius),
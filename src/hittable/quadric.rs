@@ -0,0 +1,65 @@
+use crate::{
+    geom::{Frame, Point3, Ray, Vec3},
+    hittable::HitRecord,
+    material::Material,
+};
+use ordered_float::OrderedFloat;
+use std::{f64::consts::PI, ops::Range, sync::Arc};
+
+/// An intersection with one of the surfaces of a shape that is defined in a
+/// local [`Frame`].
+pub(super) struct LocalHit {
+    pub t: f64,
+    pub normal: Vec3,
+    pub u: f64,
+    pub v: f64,
+}
+
+impl LocalHit {
+    /// Picks the closest hit that lies in `t_range`, and converts it back to
+    /// world space.
+    pub fn closest(
+        hits: Vec<LocalHit>,
+        t_range: Range<f64>,
+        ray: Ray,
+        frame: &Frame,
+        material: &Arc<dyn Material>,
+    ) -> Option<HitRecord> {
+        hits.into_iter()
+            .filter(|hit| t_range.contains(&hit.t))
+            .min_by_key(|hit| OrderedFloat(hit.t))
+            .map(|hit| {
+                HitRecord::new(
+                    ray.along(hit.t),
+                    hit.t,
+                    ray,
+                    frame.vector_to_world(hit.normal),
+                    material.clone(),
+                    hit.u,
+                    hit.v,
+                )
+            })
+    }
+}
+
+/// The angle of `p` around the local Z axis, scaled to `0..=1`.
+pub(super) fn azimuth(p: Point3) -> f64 {
+    (p.y().atan2(p.x()) + PI) / (2.0 * PI)
+}
+
+/// Intersects a ray with a disk in the plane `z = height`, centered on the
+/// local Z axis.
+pub(super) fn disk_hit(ray: Ray, height: f64, radius: f64, normal: Vec3) -> Option<LocalHit> {
+    let t = (height - ray.origin.z()) / ray.direction.z();
+    let p = ray.along(t);
+    if t.is_finite() && p.x().powi(2) + p.y().powi(2) <= radius.powi(2) {
+        Some(LocalHit {
+            t,
+            normal,
+            u: 0.5 * (p.x() / radius + 1.0),
+            v: 0.5 * (p.y() / radius + 1.0),
+        })
+    } else {
+        None
+    }
+}
@@ -0,0 +1,403 @@
+use std::{f64::consts::PI, ops::Range, sync::Arc};
+
+use crate::{
+    geom::{Aabb, Point3, Ray, Vec3},
+    material::Material,
+};
+
+use super::{HitRecord, Hittable};
+
+/// Give up marching after this many steps, treating the ray as a miss.
+const MAX_STEPS: usize = 512;
+/// How close to the surface a march has to get to count as a hit, relative
+/// to the size of the shape's bounds and their distance from the origin, so
+/// that shapes of any scale keep their detail and still converge.
+const HIT_DISTANCE: f64 = 1e-4;
+/// Offset used when estimating the gradient of the distance function,
+/// relative to the hit distance.
+const GRADIENT_STEP: f64 = 0.1;
+
+/// A node in a signed distance function expression tree. Distances are
+/// negative inside the shape.
+#[derive(Clone, Debug)]
+pub enum SdfNode {
+    Sphere {
+        radius: f64,
+    },
+    /// A box centered on the origin, with its edges rounded off by `rounding`.
+    Box {
+        half_size: Vec3,
+        rounding: f64,
+    },
+    /// A torus around the Y axis.
+    Torus {
+        major_radius: f64,
+        minor_radius: f64,
+    },
+    Mandelbulb {
+        power: f64,
+        iterations: u32,
+    },
+    /// Combining operations. A `smoothness` of zero gives sharp edges, larger
+    /// values blend the shapes together over that distance.
+    Union(Box<SdfNode>, Box<SdfNode>, f64),
+    Intersection(Box<SdfNode>, Box<SdfNode>, f64),
+    Subtraction(Box<SdfNode>, Box<SdfNode>, f64),
+    Translate {
+        offset: Vec3,
+        node: Box<SdfNode>,
+    },
+    Scale {
+        factor: f64,
+        node: Box<SdfNode>,
+    },
+    /// Repeats the node infinitely, once per `period` along each axis. A
+    /// period of zero disables repetition along that axis.
+    Repeat {
+        period: Vec3,
+        node: Box<SdfNode>,
+    },
+    /// Twists the node around the Y axis by `rate` radians per unit of height.
+    Twist {
+        rate: f64,
+        node: Box<SdfNode>,
+    },
+}
+
+impl SdfNode {
+    pub fn distance(&self, p: Point3) -> f64 {
+        match self {
+            SdfNode::Sphere { radius } => p.length() - radius,
+            SdfNode::Box {
+                half_size,
+                rounding,
+            } => {
+                let q = Vec3::new(p.x().abs(), p.y().abs(), p.z().abs()) - *half_size
+                    + Vec3::new(*rounding, *rounding, *rounding);
+                let outside = Vec3::new(q.x().max(0.0), q.y().max(0.0), q.z().max(0.0));
+                outside.length() + q.x().max(q.y()).max(q.z()).min(0.0) - rounding
+            }
+            SdfNode::Torus {
+                major_radius,
+                minor_radius,
+            } => {
+                let ring = p.x().hypot(p.z()) - major_radius;
+                ring.hypot(p.y()) - minor_radius
+            }
+            SdfNode::Mandelbulb { power, iterations } => mandelbulb(p, *power, *iterations),
+            SdfNode::Union(a, b, k) => smooth_min(a.distance(p), b.distance(p), *k),
+            SdfNode::Intersection(a, b, k) => -smooth_min(-a.distance(p), -b.distance(p), *k),
+            SdfNode::Subtraction(a, b, k) => -smooth_min(-a.distance(p), b.distance(p), *k),
+            SdfNode::Translate { offset, node } => node.distance(p - *offset),
+            SdfNode::Scale { factor, node } => node.distance(p / *factor) * factor,
+            SdfNode::Repeat { period, node } => {
+                let mut q = p;
+                for axis in 0..3 {
+                    if period[axis] > 0.0 {
+                        q[axis] -= period[axis] * (p[axis] / period[axis]).round();
+                    }
+                }
+                node.distance(q)
+            }
+            SdfNode::Twist { rate, node } => {
+                let (s, c) = (rate * p.y()).sin_cos();
+                node.distance(Vec3::new(
+                    c * p.x() - s * p.z(),
+                    p.y(),
+                    s * p.x() + c * p.z(),
+                ))
+            }
+        }
+    }
+
+    /// The bounds of the shape, or `None` if it is unbounded.
+    pub fn bounding_box(&self) -> Option<Aabb> {
+        match self {
+            SdfNode::Sphere { radius } => Some(cube(*radius)),
+            SdfNode::Box { half_size, .. } => Some(Aabb::new(-*half_size, *half_size)),
+            SdfNode::Torus {
+                major_radius,
+                minor_radius,
+            } => {
+                let r = major_radius + minor_radius;
+                Some(Aabb::new(
+                    Point3::new(-r, -minor_radius, -r),
+                    Point3::new(r, *minor_radius, r),
+                ))
+            }
+            // the bulb itself fits inside a radius of about 1.2
+            SdfNode::Mandelbulb { .. } => Some(cube(1.5)),
+            SdfNode::Union(a, b, k) => {
+                // smooth blending can bulge out a little past both shapes
+                let bbox = Aabb::surrounding(&[&a.bounding_box()?, &b.bounding_box()?])?;
+                let pad = Vec3::new(*k, *k, *k);
+                Some(Aabb::new(bbox.min - pad, bbox.max + pad))
+            }
+            SdfNode::Intersection(a, b, _) => match (a.bounding_box(), b.bounding_box()) {
+                (Some(a), Some(b)) => Some(Aabb::new(
+                    Point3::new(
+                        a.min.x().max(b.min.x()),
+                        a.min.y().max(b.min.y()),
+                        a.min.z().max(b.min.z()),
+                    ),
+                    Point3::new(
+                        a.max.x().min(b.max.x()),
+                        a.max.y().min(b.max.y()),
+                        a.max.z().min(b.max.z()),
+                    ),
+                )),
+                (bbox @ Some(_), None) | (None, bbox) => bbox,
+            },
+            SdfNode::Subtraction(a, _, _) => a.bounding_box(),
+            SdfNode::Translate { offset, node } => node
+                .bounding_box()
+                .map(|bbox| Aabb::new(bbox.min + *offset, bbox.max + *offset)),
+            SdfNode::Scale { factor, node } => node
+                .bounding_box()
+                .map(|bbox| Aabb::new(bbox.min * *factor, bbox.max * *factor)),
+            SdfNode::Repeat { .. } => None,
+            SdfNode::Twist { node, .. } => {
+                let bbox = node.bounding_box()?;
+                let r = bbox
+                    .corners()
+                    .iter()
+                    .map(|c| c.x().hypot(c.z()))
+                    .fold(0.0, f64::max);
+                Some(Aabb::new(
+                    Point3::new(-r, bbox.min.y(), -r),
+                    Point3::new(r, bbox.max.y(), r),
+                ))
+            }
+        }
+    }
+
+    /// An upper bound on how fast the function can change, relative to the
+    /// true distance. Marching steps are divided by this so that distorting
+    /// operations don't overshoot the surface.
+    fn lipschitz(&self) -> f64 {
+        match self {
+            SdfNode::Sphere { .. }
+            | SdfNode::Box { .. }
+            | SdfNode::Torus { .. }
+            | SdfNode::Mandelbulb { .. } => 1.0,
+            SdfNode::Union(a, b, _)
+            | SdfNode::Intersection(a, b, _)
+            | SdfNode::Subtraction(a, b, _) => a.lipschitz().max(b.lipschitz()),
+            SdfNode::Translate { node, .. }
+            | SdfNode::Scale { node, .. }
+            | SdfNode::Repeat { node, .. } => node.lipschitz(),
+            SdfNode::Twist { rate, node } => {
+                let r = node.bounding_box().map_or(1.0, |bbox| {
+                    bbox.corners()
+                        .iter()
+                        .map(|c| c.x().hypot(c.z()))
+                        .fold(0.0, f64::max)
+                });
+                node.lipschitz() * (1.0 + (rate * r).powi(2)).sqrt()
+            }
+        }
+    }
+
+    fn gradient(&self, p: Point3, h: f64) -> Vec3 {
+        let dx = Vec3::new(h, 0.0, 0.0);
+        let dy = Vec3::new(0.0, h, 0.0);
+        let dz = Vec3::new(0.0, 0.0, h);
+        Vec3::new(
+            self.distance(p + dx) - self.distance(p - dx),
+            self.distance(p + dy) - self.distance(p - dy),
+            self.distance(p + dz) - self.distance(p - dz),
+        ) / (2.0 * h)
+    }
+}
+
+fn cube(half: f64) -> Aabb {
+    Aabb::new(
+        Point3::new(-half, -half, -half),
+        Point3::new(half, half, half),
+    )
+}
+
+/// Polynomial smooth minimum, blending over a distance of `k`.
+fn smooth_min(a: f64, b: f64, k: f64) -> f64 {
+    if k <= 0.0 {
+        a.min(b)
+    } else {
+        let h = (0.5 + 0.5 * (b - a) / k).clamp(0.0, 1.0);
+        b + (a - b) * h - k * h * (1.0 - h)
+    }
+}
+
+/// Distance estimate for the Mandelbulb fractal.
+fn mandelbulb(p: Point3, power: f64, iterations: u32) -> f64 {
+    let mut z = p;
+    let mut dr = 1.0;
+    let mut r = z.length();
+    for _ in 0..iterations {
+        // the origin stays put, and its angles are undefined
+        if r > 2.0 || r == 0.0 {
+            break;
+        }
+        let theta = (z.z() / r).acos() * power;
+        let phi = z.y().atan2(z.x()) * power;
+        dr = r.powf(power - 1.0) * power * dr + 1.0;
+        let zr = r.powf(power);
+        z =
+            zr * Vec3::new(
+                theta.sin() * phi.cos(),
+                theta.sin() * phi.sin(),
+                theta.cos(),
+            ) + p;
+        r = z.length();
+    }
+    if r == 0.0 {
+        // deep inside the bulb, where ln(r) * r would be 0 * -inf
+        return 0.0;
+    }
+    0.5 * r.ln() * r / dr
+}
+
+/// A shape defined by a signed distance function, rendered by sphere tracing.
+#[derive(Clone)]
+pub struct Sdf {
+    root: SdfNode,
+    bounds: Aabb,
+    lipschitz: f64,
+    hit_distance: f64,
+    material: Arc<dyn Material>,
+}
+
+impl Sdf {
+    /// Creates a new shape. Unbounded shapes, such as ones using `Repeat`,
+    /// need explicit `bounds` to limit marching.
+    pub fn new(root: SdfNode, bounds: Option<Aabb>, material: Arc<dyn Material>) -> Option<Self> {
+        // pad the bounds so that surfaces touching them are still reached
+        let bounds = bounds.or_else(|| root.bounding_box())?;
+        let hit_distance = HIT_DISTANCE * bounds.min.max_abs().max(bounds.max.max_abs());
+        let pad = Vec3::new(hit_distance, hit_distance, hit_distance) * 2.0;
+        let bounds = Aabb::new(bounds.min - pad, bounds.max + pad);
+        Some(Self {
+            lipschitz: root.lipschitz(),
+            hit_distance,
+            root,
+            bounds,
+            material,
+        })
+    }
+}
+
+impl Hittable for Sdf {
+    fn hit(&self, ray: Ray, t_range: Range<f64>) -> Option<HitRecord> {
+        let t_range = self.bounds.clip(ray, t_range)?;
+        let ray_length = ray.direction.length();
+
+        // March on the absolute distance, so that rays starting inside the
+        // shape (like refracted rays) find the surface from the inside too.
+        let mut t = t_range.start;
+        for _ in 0..MAX_STEPS {
+            let distance = self.root.distance(ray.along(t)).abs();
+            if distance < self.hit_distance {
+                let p = ray.along(t);
+                let outward_normal = self
+                    .root
+                    .gradient(p, GRADIENT_STEP * self.hit_distance)
+                    .unit_vector();
+                let theta = (-outward_normal.y()).acos();
+                let phi = (-outward_normal.z()).atan2(outward_normal.x()) + PI;
+                let mut record = HitRecord::new(
+                    p,
+                    t,
+                    ray,
+                    outward_normal,
+                    self.material.clone(),
+                    phi / (2.0 * PI),
+                    theta / PI,
+                );
+                // marching stops anywhere within the hit distance, on either
+                // side, so rays spawned from here start at least that far
+                // off the surface, and can't hit it again straight away
+                record.error = record.error.max(2.0 * self.hit_distance);
+                return Some(record);
+            }
+            t += (distance / self.lipschitz).max(self.hit_distance) / ray_length;
+            if t >= t_range.end {
+                break;
+            }
+        }
+        None
+    }
+
+    fn bounding_box(&self, _time_range: Range<f64>) -> Option<Aabb> {
+        Some(self.bounds.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Sdf, SdfNode};
+    use crate::{
+        geom::{Color, Point3, Ray, Vec3},
+        hittable::Hittable,
+        material::Lambertian,
+        texture::SolidColor,
+    };
+    use std::sync::Arc;
+
+    #[test]
+    fn test_sphere_trace() {
+        let material = Arc::new(Lambertian {
+            albedo: Box::new(SolidColor(Color::white())),
+        });
+        let sdf = Sdf::new(SdfNode::Sphere { radius: 1.0 }, None, material).unwrap();
+        let ray = Ray::new(Point3::new(0.0, 0.0, -5.0), Vec3::new(0.0, 0.0, 2.0), 0.0);
+
+        let hit = sdf.hit(ray, 0.001..f64::INFINITY).unwrap();
+        assert!((hit.t - 2.0).abs() < 1e-3);
+        assert!(hit.front_face);
+        assert!((hit.normal - Vec3::new(0.0, 0.0, -1.0)).length() < 1e-3);
+
+        // continuing from inside finds the far side
+        let inside = hit.spawn_ray(ray.direction, 0.0);
+        let exit = sdf.hit(inside, 0.0..f64::INFINITY).unwrap();
+        assert!((exit.p.z() - 1.0).abs() < 1e-3);
+        assert!(!exit.front_face);
+    }
+
+    #[test]
+    fn test_scale() {
+        let material = Arc::new(Lambertian {
+            albedo: Box::new(SolidColor(Color::white())),
+        });
+        for scale in [1e-6, 1.0, 1e6] {
+            let root = SdfNode::Scale {
+                factor: scale,
+                node: Box::new(SdfNode::Sphere { radius: 1.0 }),
+            };
+            let sdf = Sdf::new(root, None, material.clone()).unwrap();
+            let ray = Ray::new(
+                Point3::new(0.0, 0.0, -5.0 * scale),
+                Vec3::new(0.0, 0.0, 1.0),
+                0.0,
+            );
+            let hit = sdf.hit(ray, 0.0..f64::INFINITY).unwrap();
+            assert!((hit.t / scale - 4.0).abs() < 1e-3, "at scale {scale}");
+
+            // rays leaving the surface, even at a grazing angle, don't hit
+            // it again where they left
+            let grazing = Vec3::new(1.0, 0.0, -0.01);
+            let leaving = hit.spawn_ray(grazing, 0.0);
+            assert!(
+                sdf.hit(leaving, 0.0..f64::INFINITY).is_none(),
+                "at scale {scale}"
+            );
+            let through = hit.spawn_ray(ray.direction, 0.0);
+            let exit = sdf.hit(through, 0.0..f64::INFINITY).unwrap();
+            assert!((exit.p.z() / scale - 1.0).abs() < 1e-3, "at scale {scale}");
+        }
+
+        let bulb = SdfNode::Mandelbulb {
+            power: 8.0,
+            iterations: 8,
+        };
+        assert!(!bulb.distance(Point3::default()).is_nan());
+    }
+}
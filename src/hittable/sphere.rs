@@ -1,6 +1,6 @@
 use crate::{
     geom::{Aabb, Point3, Ray, Vec3},
-    hittable::{HitRecord, Hittable},
+    hittable::{HitRecord, Hittable, Interval},
     material::Material,
 };
 use std::{f64::consts::PI, ops::Range, sync::Arc};
@@ -17,50 +17,68 @@ impl Sphere {
     /// calculates:
     /// - `u` a value in `0..=1`, the angle around the Y axis, from X=-1 to X=+1
     /// - `v` a value in `0..=1`, the angle around the X axis, rfom Y=-1 to Y=+1
-    fn get_uv(p: Point3) -> (f64, f64) {
+    pub(super) fn get_uv(p: Point3) -> (f64, f64) {
         let theta = (-p.y()).acos();
         let phi = (-p.z()).atan2(p.x()) + PI;
         (phi / (2.0 * PI), theta / PI)
     }
+
+    /// Where the line through the ray crosses a sphere, if it does, nearest
+    /// first.
+    pub(super) fn roots(center: Point3, radius: f64, ray: Ray) -> Option<(f64, f64)> {
+        // this is based off solving the equation for a sphere set equal to the
+        // equation of a line, which boils down to a quadratic equation.
+        let oc = ray.origin - center;
+        let a = ray.direction.length_squared();
+        let half_b = oc.dot(ray.direction);
+        let c = oc.length_squared() - radius.powi(2);
+
+        let discrim = half_b.powi(2) - a * c;
+        if discrim < 0.0 {
+            return None;
+        }
+        let sqrtd = discrim.sqrt();
+        Some(((-half_b - sqrtd) / a, (-half_b + sqrtd) / a))
+    }
+
+    fn record_at(&self, ray: Ray, t: f64) -> HitRecord {
+        let p = ray.along(t);
+        let outward_normal = (p - self.center) / self.radius;
+        let (u, v) = Self::get_uv(outward_normal);
+        HitRecord::new(p, t, ray, outward_normal, self.material.clone(), u, v)
+    }
 }
 
 impl Hittable for Sphere {
     fn hit(&self, ray: Ray, t_range: Range<f64>) -> Option<HitRecord> {
-        // this is based off solving the equation for a sphere set equal to the
-        // equation of a line, which boils down to a quadratic equation.
+        // find the nearest root that lies in `t_range`
+        let (near, far) = Self::roots(self.center, self.radius, ray)?;
+        [near, far]
+            .into_iter()
+            .find(|t| t_range.contains(t))
+            .map(|t| self.record_at(ray, t))
+    }
 
+    fn occluded(&self, ray: Ray, t_range: Range<f64>) -> bool {
+        Self::roots(self.center, self.radius, ray)
+            .is_some_and(|(near, far)| t_range.contains(&near) || t_range.contains(&far))
+    }
+
+    fn intervals(&self, ray: Ray) -> Vec<Interval> {
         let oc = ray.origin - self.center;
         let a = ray.direction.length_squared();
         let half_b = oc.dot(ray.direction);
         let c = oc.length_squared() - self.radius.powi(2);
 
         let discrim = half_b.powi(2) - a * c;
-        if discrim < 0.0 {
-            None
+        if discrim <= 0.0 {
+            vec![]
         } else {
-            // find the nearest root that lies in `t_range`
             let sqrtd = discrim.sqrt();
-
-            let mut root = (-half_b - sqrtd) / a;
-            if !t_range.contains(&root) {
-                root = (-half_b + sqrtd) / a;
-                if !t_range.contains(&root) {
-                    return None;
-                }
-            }
-            let t = root;
-            let p = ray.along(t);
-            let outward_normal = (p - self.center) / self.radius;
-            let (u, v) = Self::get_uv(outward_normal);
-            Some(HitRecord::new(
-                p,
-                t,
-                ray,
-                outward_normal,
-                self.material.clone(),
-                u,
-                v,
-            ))
+            vec![Interval {
+                enter: self.record_at(ray, (-half_b - sqrtd) / a),
+                exit: self.record_at(ray, (-half_b + sqrtd) / a),
+            }]
         }
     }
 
@@ -0,0 +1,451 @@
+use anyhow::{anyhow, ensure, Context, Result};
+use ordered_float::OrderedFloat;
+use std::{fs, ops::Range, path::Path, sync::Arc};
+
+use crate::{
+    geom::{Aabb, Axis, Point3, Ray, SlabRay, Vec3},
+    material::Material,
+};
+
+use super::{HitRecord, Hittable, Sphere};
+
+/// How many spheres each leaf of the BVH holds. A leaf's spheres are tested
+/// together, in a loop without branches that the compiler can vectorize.
+const LANES: usize = 8;
+
+/// Trees are never deeper than this, so traversal can keep its stack in a
+/// fixed size array.
+const MAX_DEPTH: usize = 48;
+
+/// How many bytes each sphere takes up in a binary particle file.
+const RECORD_SIZE: usize = 20;
+
+/// A large number of spheres, such as the particles of a simulation. Rather
+/// than being separate objects, their centers, radii and materials are kept
+/// in flat arrays, ordered so that each leaf of their BVH is a contiguous run.
+#[derive(Clone)]
+pub struct SphereSet {
+    data: Arc<SphereData>,
+}
+
+struct SphereData {
+    x: Vec<f64>,
+    y: Vec<f64>,
+    z: Vec<f64>,
+    radius: Vec<f64>,
+    material: Vec<u32>,
+    materials: Vec<Arc<dyn Material>>,
+    nodes: Vec<Node>,
+}
+
+struct Node {
+    bounding_box: Aabb,
+    kind: NodeKind,
+}
+
+enum NodeKind {
+    Leaf {
+        first: usize,
+        count: usize,
+    },
+    /// The first child is the next node in the array.
+    Interior {
+        second_child: usize,
+        axis: Axis,
+    },
+}
+
+/// One sphere in a set, with its material given as an index into the set's
+/// list of materials.
+#[derive(Clone, Copy, Debug)]
+pub struct Particle {
+    pub center: Point3,
+    pub radius: f64,
+    pub material: u32,
+}
+
+impl Particle {
+    fn bounding_box(&self) -> Aabb {
+        let extent = Vec3::new(self.radius, self.radius, self.radius);
+        Aabb::new(self.center - extent, self.center + extent)
+    }
+}
+
+impl SphereSet {
+    pub fn new(mut particles: Vec<Particle>, materials: Vec<Arc<dyn Material>>) -> Result<Self> {
+        ensure!(!particles.is_empty(), "there are no particles");
+        if let Some(particle) = particles
+            .iter()
+            .find(|p| p.material as usize >= materials.len())
+        {
+            return Err(anyhow!(
+                "a particle uses material {}, but there are only {} materials",
+                particle.material,
+                materials.len()
+            ));
+        }
+
+        let mut nodes = vec![];
+        build(&mut particles, 0, &mut nodes, 0);
+
+        Ok(Self {
+            data: Arc::new(SphereData {
+                x: particles.iter().map(|p| p.center.x()).collect(),
+                y: particles.iter().map(|p| p.center.y()).collect(),
+                z: particles.iter().map(|p| p.center.z()).collect(),
+                radius: particles.iter().map(|p| p.radius).collect(),
+                material: particles.iter().map(|p| p.material).collect(),
+                materials,
+                nodes,
+            }),
+        })
+    }
+
+    /// Loads particles from a file. Files ending in `.csv` hold one particle
+    /// per line as `x,y,z,radius` and optionally a material index, which
+    /// defaults to 0. Lines starting with `#` are ignored, as is a header line.
+    ///
+    /// Any other file is read as binary, as a sequence of records of `x`, `y`,
+    /// `z` and `radius` as little endian 32 bit floats, followed by the
+    /// material index as a little endian 32 bit unsigned integer.
+    pub fn load(path: &Path, materials: Vec<Arc<dyn Material>>) -> Result<Self> {
+        let particles = if path.extension().is_some_and(|e| e == "csv") {
+            parse_csv(&fs::read_to_string(path)?)?
+        } else {
+            parse_binary(&fs::read(path)?)?
+        };
+        Self::new(particles, materials)
+    }
+
+    fn record_at(&self, ray: Ray, t: f64, index: usize) -> HitRecord {
+        let data = &self.data;
+        let center = Point3::new(data.x[index], data.y[index], data.z[index]);
+        let p = ray.along(t);
+        let outward_normal = (p - center) / data.radius[index];
+        let (u, v) = Sphere::get_uv(outward_normal);
+        let material = data.materials[data.material[index] as usize].clone();
+        HitRecord::new(p, t, ray, outward_normal, material, u, v)
+    }
+
+    /// Tests the ray against every sphere in a leaf at once, returning the
+    /// distance to each, or infinity for those it misses.
+    fn hit_leaf(&self, ray: Ray, first: usize, count: usize, t_range: &Range<f64>) -> [f64; LANES] {
+        let data = &self.data;
+        let leaf = first..first + count;
+        let a = ray.direction.length_squared();
+        let mut hits = [f64::INFINITY; LANES];
+
+        let spheres = data.x[leaf.clone()]
+            .iter()
+            .zip(&data.y[leaf.clone()])
+            .zip(&data.z[leaf.clone()])
+            .zip(&data.radius[leaf]);
+        for (t, (((x, y), z), radius)) in hits.iter_mut().zip(spheres) {
+            let oc = ray.origin - Point3::new(*x, *y, *z);
+            let half_b = oc.dot(ray.direction);
+            let c = oc.length_squared() - radius * radius;
+            let discrim = half_b * half_b - a * c;
+            let sqrtd = discrim.max(0.0).sqrt();
+            let near = (-half_b - sqrtd) / a;
+            let far = (-half_b + sqrtd) / a;
+            *t = if discrim < 0.0 {
+                f64::INFINITY
+            } else if t_range.contains(&near) {
+                near
+            } else if t_range.contains(&far) {
+                far
+            } else {
+                f64::INFINITY
+            };
+        }
+        hits
+    }
+
+    /// Walks the leaves that the ray reaches, front to back, handing them to
+    /// `leaf` along with the part of `t_range` that is left. `leaf` returns
+    /// the new end of the range, or `None` to stop.
+    fn traverse(
+        &self,
+        ray: Ray,
+        t_range: Range<f64>,
+        mut leaf: impl FnMut(usize, usize, Range<f64>) -> Option<f64>,
+    ) {
+        let nodes = &self.data.nodes;
+        let slab_ray = SlabRay::from(ray);
+        let mut t_max = t_range.end;
+        let mut stack = [0; MAX_DEPTH];
+        let mut stack_len = 0;
+        let mut index = 0;
+
+        loop {
+            let node = &nodes[index];
+            if node
+                .bounding_box
+                .clip_slab(&slab_ray, t_range.start..t_max)
+                .is_some()
+            {
+                match node.kind {
+                    NodeKind::Leaf { first, count } => {
+                        match leaf(first, count, t_range.start..t_max) {
+                            Some(t) => t_max = t,
+                            None => return,
+                        }
+                    }
+                    NodeKind::Interior { second_child, axis } => {
+                        let (near, far) = if slab_ray.inv_direction[axis] < 0.0 {
+                            (second_child, index + 1)
+                        } else {
+                            (index + 1, second_child)
+                        };
+                        stack[stack_len] = far;
+                        stack_len += 1;
+                        index = near;
+                        continue;
+                    }
+                }
+            }
+
+            if stack_len == 0 {
+                return;
+            }
+            stack_len -= 1;
+            index = stack[stack_len];
+        }
+    }
+}
+
+/// Builds the BVH over `particles`, which start at `offset` in the whole set,
+/// reordering them so each leaf's particles are next to each other. Returns
+/// the index of the new node.
+fn build(particles: &mut [Particle], offset: usize, nodes: &mut Vec<Node>, depth: usize) -> usize {
+    let bounding_box = particles
+        .iter()
+        .map(Particle::bounding_box)
+        .reduce(|a, b| a.union(&b))
+        .expect("nodes are never empty");
+    let index = nodes.len();
+    nodes.push(Node {
+        bounding_box,
+        kind: NodeKind::Leaf {
+            first: offset,
+            count: particles.len(),
+        },
+    });
+    if particles.len() <= LANES {
+        return index;
+    }
+
+    // split at the median along the axis the centers are most spread out on
+    let centers = particles
+        .iter()
+        .map(|p| Aabb::new(p.center, p.center))
+        .reduce(|a, b| a.union(&b))
+        .expect("nodes are never empty");
+    let span = centers.span();
+    let axis = [Axis::X, Axis::Y, Axis::Z]
+        .into_iter()
+        .max_by_key(|a| OrderedFloat(span[*a]))
+        .expect("there are three axes");
+    if depth + 1 >= MAX_DEPTH || span[axis] <= 0.0 {
+        // the leaf may have more than `LANES` spheres, but they are all in
+        // the same place, so it will be tested a few lanes at a time
+        return index;
+    }
+
+    let middle = particles.len() / 2;
+    particles.select_nth_unstable_by_key(middle, |p| OrderedFloat(p.center[axis]));
+    let (left, right) = particles.split_at_mut(middle);
+    build(left, offset, nodes, depth + 1);
+    let second_child = build(right, offset + middle, nodes, depth + 1);
+    nodes[index].kind = NodeKind::Interior { second_child, axis };
+    index
+}
+
+/// Splits a leaf into runs of at most `LANES` spheres. Only leaves whose
+/// spheres are all in the same place have more than that.
+fn batches(first: usize, count: usize) -> impl Iterator<Item = (usize, usize)> {
+    let end = first + count;
+    (first..end)
+        .step_by(LANES)
+        .map(move |start| (start, (end - start).min(LANES)))
+}
+
+fn parse_csv(text: &str) -> Result<Vec<Particle>> {
+    let mut particles = vec![];
+    for (line_number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields = line.split(',').map(str::trim).collect::<Vec<_>>();
+        ensure!(
+            (4..=5).contains(&fields.len()),
+            "line {} should have 4 or 5 fields, but has {}",
+            line_number + 1,
+            fields.len()
+        );
+        let values = fields[..4]
+            .iter()
+            .map(|f| f.parse::<f64>())
+            .collect::<Result<Vec<_>, _>>();
+        let values = match values {
+            Ok(values) => values,
+            // a header, naming the columns
+            Err(_)
+                if particles.is_empty()
+                    && fields.iter().any(|f| f.starts_with(char::is_alphabetic)) =>
+            {
+                continue;
+            }
+            Err(err) => {
+                return Err(err).with_context(|| format!("parsing line {}", line_number + 1));
+            }
+        };
+        let material = match fields.get(4) {
+            Some(field) => field
+                .parse()
+                .with_context(|| format!("parsing material on line {}", line_number + 1))?,
+            None => 0,
+        };
+        particles.push(Particle {
+            center: Point3::new(values[0], values[1], values[2]),
+            radius: values[3],
+            material,
+        });
+    }
+    Ok(particles)
+}
+
+fn parse_binary(bytes: &[u8]) -> Result<Vec<Particle>> {
+    ensure!(
+        bytes.len().is_multiple_of(RECORD_SIZE),
+        "file is {} bytes long, which isn't a whole number of {} byte records",
+        bytes.len(),
+        RECORD_SIZE
+    );
+    Ok(bytes
+        .chunks_exact(RECORD_SIZE)
+        .map(|record| {
+            let word = |i: usize| {
+                let mut word = [0; 4];
+                word.copy_from_slice(&record[i * 4..i * 4 + 4]);
+                word
+            };
+            let float = |i: usize| f64::from(f32::from_le_bytes(word(i)));
+            Particle {
+                center: Point3::new(float(0), float(1), float(2)),
+                radius: float(3),
+                material: u32::from_le_bytes(word(4)),
+            }
+        })
+        .collect())
+}
+
+impl Hittable for SphereSet {
+    fn hit(&self, ray: Ray, t_range: Range<f64>) -> Option<HitRecord> {
+        let mut closest: Option<(usize, f64)> = None;
+        self.traverse(ray, t_range, |first, count, mut t_range| {
+            for (start, count) in batches(first, count) {
+                let hits = self.hit_leaf(ray, start, count, &t_range);
+                for (lane, t) in hits.into_iter().enumerate() {
+                    if t < t_range.end {
+                        t_range.end = t;
+                        closest = Some((start + lane, t));
+                    }
+                }
+            }
+            Some(t_range.end)
+        });
+        closest.map(|(index, t)| self.record_at(ray, t, index))
+    }
+
+    fn occluded(&self, ray: Ray, t_range: Range<f64>) -> bool {
+        let mut occluded = false;
+        self.traverse(ray, t_range, |first, count, t_range| {
+            occluded = batches(first, count).any(|(start, count)| {
+                self.hit_leaf(ray, start, count, &t_range)
+                    .iter()
+                    .any(|t| t.is_finite())
+            });
+            (!occluded).then_some(t_range.end)
+        });
+        occluded
+    }
+
+    fn bounding_box(&self, _time_range: Range<f64>) -> Option<Aabb> {
+        Some(self.data.nodes[0].bounding_box.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{hittable::HittableList, material::Lambertian, texture::SolidColor};
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    #[test]
+    fn test_matches_spheres() {
+        let mut rng = StdRng::seed_from_u64(11);
+        let materials: Vec<Arc<dyn Material>> = (0..3)
+            .map(|i| {
+                Arc::new(Lambertian {
+                    albedo: Box::new(SolidColor(Vec3::new(f64::from(i), 0.0, 0.0))),
+                }) as Arc<dyn Material>
+            })
+            .collect();
+        let particles = (0..2000)
+            .map(|_| Particle {
+                center: Point3::new(
+                    rng.gen_range(-10.0..10.0),
+                    rng.gen_range(-10.0..10.0),
+                    rng.gen_range(-10.0..10.0),
+                ),
+                radius: rng.gen_range(0.05..0.5),
+                material: rng.gen_range(0..3),
+            })
+            .collect::<Vec<_>>();
+
+        let list = particles
+            .iter()
+            .map(|p| Sphere {
+                center: p.center,
+                radius: p.radius,
+                material: materials[p.material as usize].clone(),
+            })
+            .collect::<HittableList>();
+        let set = SphereSet::new(particles, materials).unwrap();
+
+        for _ in 0..1000 {
+            let ray = Ray::new(
+                Vec3::new(0.0, 0.0, 30.0),
+                Vec3::new(rng.gen_range(-0.5..0.5), rng.gen_range(-0.5..0.5), -1.0),
+                0.0,
+            );
+            let expected = list.hit(ray, 0.001..f64::INFINITY);
+            let hit = set.hit(ray, 0.001..f64::INFINITY);
+            assert_eq!(hit.as_ref().map(|h| h.t), expected.as_ref().map(|h| h.t));
+            if let (Some(hit), Some(expected)) = (hit, expected) {
+                assert!(std::ptr::addr_eq(
+                    Arc::as_ptr(&hit.material),
+                    Arc::as_ptr(&expected.material)
+                ));
+            }
+            assert_eq!(
+                set.occluded(ray, 0.001..30.0),
+                list.occluded(ray, 0.001..30.0)
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_csv() {
+        let particles =
+            parse_csv("x,y,z,radius,material\n# a comment\n1,2,3,0.5\n4, 5, 6, 1, 2\n").unwrap();
+        assert_eq!(particles.len(), 2);
+        assert_eq!(particles[0].material, 0);
+        assert_eq!(particles[1].center, Point3::new(4.0, 5.0, 6.0));
+        assert_eq!(particles[1].material, 2);
+        assert!(parse_csv("1,2,3\n").is_err());
+        assert!(parse_csv("1,2,3,0.5,0,7\n").is_err());
+    }
+}
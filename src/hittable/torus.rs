@@ -0,0 +1,96 @@
+use std::{f64::consts::PI, ops::Range, sync::Arc};
+
+use crate::{
+    geom::{Aabb, Frame, Point3, Ray, Vec3},
+    material::Material,
+    polynomial::solve_quartic,
+};
+
+use super::{
+    quadric::{azimuth, LocalHit},
+    HitRecord, Hittable,
+};
+
+/// A ring shaped torus around `axis`. `major_radius` is the distance from the
+/// center to the middle of the tube, and `minor_radius` is the radius of the
+/// tube itself.
+#[derive(Clone)]
+pub struct Torus {
+    frame: Frame,
+    major_radius: f64,
+    minor_radius: f64,
+    material: Arc<dyn Material>,
+}
+
+impl Torus {
+    pub fn new(
+        center: Point3,
+        axis: Vec3,
+        major_radius: f64,
+        minor_radius: f64,
+        material: Arc<dyn Material>,
+    ) -> Self {
+        Self {
+            frame: Frame::new(center, axis),
+            major_radius,
+            minor_radius,
+            material,
+        }
+    }
+}
+
+impl Hittable for Torus {
+    fn hit(&self, ray: Ray, t_range: Range<f64>) -> Option<HitRecord> {
+        let local = self.frame.ray_to_local(ray);
+        let big_r = self.major_radius;
+        let small_r = self.minor_radius;
+
+        // The quartic is badly conditioned far away from the torus, so solve
+        // it along a unit length direction starting from the point on the ray
+        // closest to the center.
+        let scale = local.direction.length();
+        let d = local.direction / scale;
+        let shift = -local.origin.dot(d);
+        let o = local.origin + shift * d;
+        if o.length() > big_r + small_r {
+            return None;
+        }
+
+        let od = o.dot(d);
+        let e = o.length_squared() + big_r.powi(2) - small_r.powi(2);
+        let four_r2 = 4.0 * big_r.powi(2);
+        let roots = solve_quartic(
+            1.0,
+            4.0 * od,
+            4.0 * od.powi(2) + 2.0 * e - four_r2 * (d.x().powi(2) + d.y().powi(2)),
+            4.0 * od * e - 2.0 * four_r2 * (o.x() * d.x() + o.y() * d.y()),
+            e.powi(2) - four_r2 * (o.x().powi(2) + o.y().powi(2)),
+        );
+
+        let hits = roots
+            .into_iter()
+            .map(|root| {
+                let t = (root + shift) / scale;
+                let p = local.along(t);
+                let rho = p.x().hypot(p.y());
+                let ring = Vec3::new(p.x(), p.y(), 0.0) * (big_r / rho);
+                LocalHit {
+                    t,
+                    normal: (p - ring).unit_vector(),
+                    u: azimuth(p),
+                    v: (p.z().atan2(rho - big_r) + PI) / (2.0 * PI),
+                }
+            })
+            .collect();
+
+        LocalHit::closest(hits, t_range, ray, &self.frame, &self.material)
+    }
+
+    fn bounding_box(&self, _time_range: Range<f64>) -> Option<Aabb> {
+        let r = self.major_radius + self.minor_radius;
+        self.frame.bounding_box(&Aabb::new(
+            Point3::new(-r, -r, -self.minor_radius),
+            Point3::new(r, r, self.minor_radius),
+        ))
+    }
+}
@@ -9,19 +9,24 @@
     clippy::cast_lossless,
 )]
 
+mod atmosphere;
 mod camera;
 mod config;
 mod geom;
 mod hittable;
 mod interpolate;
 mod material;
+mod polynomial;
 mod scene;
 mod texture;
 
 use crate::{
-    config::Scene,
-    geom::{Color, Ray},
-    hittable::BvhNode,
+    atmosphere::Atmosphere,
+    camera::Camera,
+    config::{Image, Scene},
+    geom::{Color, Ray, Vec3},
+    hittable::{BvhBuilder, BvhNode, BvhOptions},
+    material::{Media, ScatterResult},
     scene::SceneLoader,
 };
 use anyhow::{Context, Result};
@@ -30,37 +35,155 @@ use hittable::Hittable;
 use indicatif::{ParallelProgressIterator, ProgressBar, ProgressStyle};
 use pix::rgb::SRgb8;
 use png_pong::PngRaster;
-use rand::{distributions, prelude::Distribution};
-use rayon::prelude::ParallelIterator;
+use rand::{distributions, prelude::Distribution, Rng};
+use rayon::prelude::{IntoParallelIterator, ParallelIterator};
 use std::{
-    path::PathBuf,
+    path::{Path, PathBuf},
     time::{self, Duration},
 };
 
 #[derive(Parser)]
 struct Args {
     path: PathBuf,
+
+    /// How to work out the color seen along each camera ray
+    #[arg(long, value_enum, default_value_t)]
+    integrator: Integrator,
+
+    /// How far ambient occlusion looks for objects blocking the sky
+    #[arg(long, default_value_t = f64::INFINITY)]
+    ao_distance: f64,
+
+    /// How to split objects when building the BVH
+    #[arg(long, value_enum, default_value_t)]
+    bvh_builder: BvhBuilder,
+
+    /// BVH nodes with at most this many objects are never split
+    #[arg(long, default_value_t = BvhOptions::default().max_leaf_size)]
+    bvh_leaf_size: usize,
+
+    /// When rendering an animation, rebuild the BVH instead of refitting it
+    /// once its estimated cost has grown by this factor (`inf` to never
+    /// rebuild)
+    #[arg(long, default_value_t = BvhOptions::default().rebuild_threshold)]
+    bvh_rebuild_threshold: f64,
+
+    /// Print statistics about the world's BVH
+    #[arg(long)]
+    bvh_stats: bool,
+
+    /// Render an animation, by splitting the camera's shutter time between
+    /// this many frames, which are saved as image-0000.png and so on
+    #[arg(long, default_value_t = 1)]
+    frames: u32,
+
+    /// Time how quickly camera rays can be traced through the scene, without
+    /// shading them, instead of rendering it
+    #[arg(long)]
+    benchmark: bool,
+}
+
+#[derive(Clone, Copy, Default, clap::ValueEnum)]
+enum Integrator {
+    /// Follows rays as they bounce around the scene
+    #[default]
+    Path,
+    /// Shades surfaces by how much of the sky they can see, ignoring
+    /// materials and lights
+    AmbientOcclusion,
 }
 
 fn main() -> Result<()> {
     let args = Args::parse();
 
     // Scene
-    let loader = SceneLoader::new(&args.path);
+    let bvh_options = BvhOptions {
+        builder: args.bvh_builder,
+        max_leaf_size: args.bvh_leaf_size,
+        rebuild_threshold: args.bvh_rebuild_threshold,
+    };
+    let loader = SceneLoader::new(&args.path).bvh_options(bvh_options.clone());
+    let load_start = time::Instant::now();
     let Scene {
-        world,
-        camera,
+        mut world,
+        mut camera,
         image,
         background,
+        atmosphere,
+        build_time,
     } = loader.load()?;
+    println!(
+        "Loaded scene in {} (BVH built in {})",
+        human_duration(load_start.elapsed()),
+        human_duration(build_time),
+    );
+    if args.bvh_stats {
+        println!("BVH: {}", world.stats());
+    }
+    if args.benchmark {
+        benchmark(&world, &camera, &image);
+        return Ok(());
+    }
+
+    let shade = |ray: Ray, world: &BvhNode| match args.integrator {
+        Integrator::Path => {
+            ray_color(
+                ray,
+                background,
+                atmosphere.as_ref(),
+                world,
+                &Media::default(),
+                image.max_depth,
+            )
+        }
+        Integrator::AmbientOcclusion => ambient_occlusion(ray, background, world, args.ao_distance),
+    };
+
+    if args.frames <= 1 {
+        let raster = render(&world, &camera, &image, &shade)?;
+        return save(raster, Path::new("image.png"));
+    }
+
+    let shutter_time = camera.shutter_time.clone();
+    let frame_length = (shutter_time.end - shutter_time.start) / f64::from(args.frames);
+    for frame in 0..args.frames {
+        let frame_start = shutter_time.start + f64::from(frame) * frame_length;
+        camera.shutter_time = frame_start..frame_start + frame_length;
+
+        let update_start = time::Instant::now();
+        let rebuilt = world.update(camera.shutter_time.clone(), &bvh_options);
+        println!(
+            "Frame {}/{}: {} BVH in {}",
+            frame + 1,
+            args.frames,
+            if rebuilt { "rebuilt" } else { "refit" },
+            human_duration(update_start.elapsed()),
+        );
+        if args.bvh_stats {
+            println!("BVH: {}", world.stats());
+        }
 
-    // Render
+        let raster = render(&world, &camera, &image, &shade)?;
+        save(raster, Path::new(&format!("image-{frame:04}.png")))?;
+    }
+
+    Ok(())
+}
+
+/// Renders one image of the world, as seen by the camera, using `shade` to
+/// find the color seen along each ray.
+fn render(
+    world: &BvhNode,
+    camera: &Camera,
+    image: &Image,
+    shade: &(impl Fn(Ray, &BvhNode) -> Color + Sync),
+) -> Result<pix::Raster<SRgb8>> {
     let bar = ProgressBar::new(image.height as u64 * image.width as u64);
     bar.set_style(ProgressStyle::with_template(
         "{bar} {human_pos}/{human_len} ({percent}%) {elapsed_precise}",
     )?);
 
-    let start = time::Instant::now();
+    let render_start = time::Instant::now();
 
     let mut raster = pix::Raster::<SRgb8>::with_clear(image.width, image.height);
 
@@ -76,7 +199,7 @@ fn main() -> Result<()> {
         .collect::<Vec<_>>();
     let work = ParallelWorkItem {
         pixels: &mut pixels[..],
-        world,
+        world: world.clone(),
     };
 
     rayon::iter::split(work, split_pixels)
@@ -92,15 +215,20 @@ fn main() -> Result<()> {
                         let v = ((image.height as usize - *y) as f64 + jy)
                             / (image.height as f64 - 1.0);
                         let ray = camera.get_ray(u, v);
-                        ray_color(ray, background, &world, image.max_depth)
+                        shade(ray, &world)
                     })
                     .sum();
                 **pixel = color.into_srgb8(image.samples_per_pixel);
                 bar.inc(1);
             }
         });
+    println!("Rendered in {}", human_duration(render_start.elapsed()));
+
+    Ok(raster)
+}
 
-    // Saving raster as a PNG file
+/// Saves a raster as a PNG file.
+fn save(raster: pix::Raster<SRgb8>, path: &Path) -> Result<()> {
     let png_raster = PngRaster::Rgb8(raster);
     let mut out_data = Vec::new();
     let mut encoder = png_pong::Encoder::new(&mut out_data).into_step_enc();
@@ -109,33 +237,146 @@ fn main() -> Result<()> {
         delay: 0,
     };
     encoder.encode(&step).context("Adding frame to png")?;
-    std::fs::write("image.png", out_data).context("Saving image")?;
-
-    let duration = time::Instant::now().saturating_duration_since(start);
-    println!("Done in {}", human_duration(duration));
-
-    Ok(())
+    std::fs::write(path, out_data).context("Saving image")
 }
 
-fn ray_color<H: Hittable>(ray: Ray, background: Color, hittable: &H, depth_budget: u32) -> Color {
+/// Follows a ray around the scene. `media` are the objects the ray is
+/// inside, if any.
+fn ray_color<H: Hittable>(
+    ray: Ray,
+    background: Color,
+    atmosphere: Option<&Atmosphere>,
+    hittable: &H,
+    media: &Media,
+    depth_budget: u32,
+) -> Color {
     if depth_budget == 0 {
-        Color::default()
-    } else if let Some(hit_record) = hittable.hit(ray, 0.001..f64::INFINITY) {
-        let scatter_record = {
-            let material = hit_record.material.clone();
-            material.scatter(&ray, &hit_record)
+        return Color::default();
+    }
+
+    // rays leaving surfaces are spawned clear of them, so nothing needs to
+    // be skipped at the start
+    let t_min = 0.0;
+    let mut hit = hittable.hit(ray, t_min..f64::INFINITY);
+    let mut weight = Color::white();
+    if let Some(atmosphere) = atmosphere {
+        let t_max = hit.as_ref().map_or(f64::INFINITY, |h| h.t);
+        let passage = atmosphere.pass(ray, t_min, t_max);
+        weight = passage.weight;
+        if passage.scatter.is_some() {
+            hit = passage.scatter;
+        }
+    }
+    // glowing media shine all along the ray, up to wherever it next scatters
+    // or meets a surface. The atmosphere and absorbing interiors only dim
+    // this glow through the chance of the ray scattering before it.
+    let t_max = hit.as_ref().map_or(f64::INFINITY, |h| h.t);
+    let glow = hittable.emission(ray, t_min..t_max);
+
+    let color = if let Some(hit_record) = hit {
+        if let Some(interior) = media.current() {
+            weight *= interior.transmittance(hit_record.t * ray.direction.length());
+        }
+        let material = hit_record.material.clone();
+        let crossing = material
+            .interior()
+            .map(|inside| media.cross(&material, inside, hit_record.front_face));
+        let scatter_record = match crossing {
+            // the surface is inside something that takes priority over it,
+            // so the ray carries straight on
+            Some((_, None)) => ScatterResult {
+                attenuation: Color::white(),
+                scattered_ray: Some(hit_record.spawn_ray(ray.direction, ray.time)),
+            },
+            Some((_, Some(refraction_ratio))) => {
+                material.scatter_across(&ray, &hit_record, refraction_ratio)
+            }
+            None => material.scatter(&ray, &hit_record),
         };
         let emitted = hit_record
             .material
             .emitted(hit_record.u, hit_record.v, hit_record.p);
         if let Some(scattered) = scatter_record.scattered_ray {
-            let bounce_color = ray_color(scattered, background, hittable, depth_budget - 1);
+            // passing through a surface moves the ray into, or back out of,
+            // what it encloses
+            let beyond = match crossing {
+                Some((beyond, _)) if scattered.direction.dot(hit_record.normal) < 0.0 => beyond,
+                _ => media.clone(),
+            };
+            let bounce_color = ray_color(
+                scattered,
+                background,
+                atmosphere,
+                hittable,
+                &beyond,
+                depth_budget - 1,
+            );
             emitted + scatter_record.attenuation * bounce_color
         } else {
             emitted
         }
     } else {
         background
+    };
+    glow + weight * color
+}
+
+/// Traces every camera ray the render would, and reports how many hit
+/// something and how long that took.
+fn benchmark(world: &BvhNode, camera: &Camera, image: &Image) {
+    let start = time::Instant::now();
+    let hits: u64 = (0..image.height)
+        .into_par_iter()
+        .map(|y| {
+            let mut rng = rand::thread_rng();
+            let mut hits = 0;
+            for x in 0..image.width {
+                for _ in 0..image.samples_per_pixel {
+                    let u = (x as f64 + rng.gen::<f64>()) / (image.width as f64 - 1.0);
+                    let v = ((image.height - y) as f64 + rng.gen::<f64>())
+                        / (image.height as f64 - 1.0);
+                    let ray = camera.get_ray(u, v);
+                    if world.hit(ray, 0.0..f64::INFINITY).is_some() {
+                        hits += 1;
+                    }
+                }
+            }
+            hits
+        })
+        .sum();
+    let duration = time::Instant::now().saturating_duration_since(start);
+
+    let rays = image.width as u64 * image.height as u64 * image.samples_per_pixel as u64;
+    println!(
+        "Traced {rays} rays ({:.1}% hit) in {}, {:.2}M rays/s",
+        hits as f64 / rays as f64 * 100.0,
+        human_duration(duration),
+        rays as f64 / duration.as_secs_f64() / 1e6,
+    );
+}
+
+/// Casts one visibility ray per sample from the surface the camera ray hits,
+/// in a cosine weighted direction, and shades the surface white if nothing
+/// blocks it.
+fn ambient_occlusion<H: Hittable>(
+    ray: Ray,
+    background: Color,
+    hittable: &H,
+    distance: f64,
+) -> Color {
+    let Some(hit) = hittable.hit(ray, 0.0..f64::INFINITY) else {
+        return background;
+    };
+
+    let mut direction = hit.normal + Vec3::rand_unit_vector();
+    if direction.is_near_zero() {
+        direction = hit.normal;
+    }
+    let visibility_ray = hit.spawn_ray(direction.unit_vector(), ray.time);
+    if hittable.occluded(visibility_ray, 0.0..distance) {
+        Color::black()
+    } else {
+        Color::white()
     }
 }
 
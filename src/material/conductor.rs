@@ -0,0 +1,197 @@
+use crate::{
+    geom::{Color, Frame, Ray, Vec3},
+    hittable::HitRecord,
+    material::{microfacet::Ggx, Evaluation, Material, ScatterResult},
+};
+
+/// Views more grazing than this are treated as this, since shading normals
+/// can face slightly away from the ray.
+const MIN_COSINE: f64 = 1e-4;
+
+/// The complex index of refraction of a metal, per color channel: `eta` is
+/// the real part, and `k` how strongly the metal absorbs light.
+#[derive(Clone, Copy, Debug)]
+pub struct ComplexIor {
+    pub eta: Color,
+    pub k: Color,
+}
+
+impl ComplexIor {
+    // measured values, at roughly the wavelengths of red, green and blue
+    pub fn gold() -> Self {
+        Self {
+            eta: Color::new(0.143, 0.374, 1.442),
+            k: Color::new(3.983, 2.386, 1.603),
+        }
+    }
+
+    pub fn copper() -> Self {
+        Self {
+            eta: Color::new(0.200, 0.924, 1.102),
+            k: Color::new(3.912, 2.452, 2.142),
+        }
+    }
+
+    pub fn aluminium() -> Self {
+        Self {
+            eta: Color::new(1.657, 0.880, 0.521),
+            k: Color::new(9.224, 6.270, 4.837),
+        }
+    }
+
+    pub fn silver() -> Self {
+        Self {
+            eta: Color::new(0.155, 0.117, 0.138),
+            k: Color::new(4.828, 3.122, 2.147),
+        }
+    }
+
+    /// How much light reflects off the metal, from the full Fresnel
+    /// equations for conductors.
+    fn fresnel(&self, cos_i: f64) -> Color {
+        let fresnel = |eta: f64, k: f64| {
+            let cos2 = cos_i.powi(2);
+            let sin2 = 1.0 - cos2;
+            let t0 = eta.powi(2) - k.powi(2) - sin2;
+            let a2_plus_b2 = (t0.powi(2) + 4.0 * eta.powi(2) * k.powi(2)).sqrt();
+            let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+            let t1 = a2_plus_b2 + cos2;
+            let t2 = 2.0 * cos_i * a;
+            let rs = (t1 - t2) / (t1 + t2);
+            let t3 = cos2 * a2_plus_b2 + sin2.powi(2);
+            let t4 = t2 * sin2;
+            let rp = rs * (t3 - t4) / (t3 + t4);
+            0.5 * (rs + rp)
+        };
+        Color::new(
+            fresnel(self.eta.r(), self.k.r()),
+            fresnel(self.eta.g(), self.k.g()),
+            fresnel(self.eta.b(), self.k.b()),
+        )
+    }
+}
+
+/// A rough metal, with GGX microfacets, which reflects exactly as much light
+/// as the Fresnel equations and shadowing between microfacets allow.
+pub struct Conductor {
+    ior: ComplexIor,
+    ggx: Ggx,
+}
+
+impl Conductor {
+    /// `anisotropy`, from 0 to 1, stretches highlights along the surface's
+    /// tangent, or some direction across it if it doesn't have one.
+    pub fn new(ior: ComplexIor, roughness: f64, anisotropy: f64) -> Self {
+        // the same remapping as the Disney BRDF
+        let aspect = (1.0 - 0.9 * anisotropy.clamp(0.0, 1.0)).sqrt();
+        let alpha = roughness.powi(2);
+        Self {
+            ior,
+            ggx: Ggx::anisotropic(alpha / aspect, alpha * aspect),
+        }
+    }
+
+    /// The frame anisotropic roughness is measured in, and the direction
+    /// back along the ray in it.
+    fn local(ray_in: &Ray, hit_record: &HitRecord) -> (Frame, Vec3) {
+        let frame = match hit_record.tangent {
+            Some(tangent) => Frame::with_tangent(hit_record.p, hit_record.normal, tangent),
+            None => Frame::new(hit_record.p, hit_record.normal),
+        };
+        let wo = frame.vector_to_local(-ray_in.direction.unit_vector());
+        let wo = Vec3::new(wo.x(), wo.y(), wo.z().max(MIN_COSINE)).unit_vector();
+        (frame, wo)
+    }
+}
+
+impl Material for Conductor {
+    fn scatter(&self, ray_in: &Ray, hit_record: &HitRecord) -> ScatterResult {
+        let (frame, wo) = Self::local(ray_in, hit_record);
+        let m = self.ggx.sample_visible_normal(wo, &mut rand::thread_rng());
+        let wi = 2.0 * wo.dot(m) * m - wo;
+        if wi.z() <= 0.0 {
+            return ScatterResult {
+                attenuation: Color::black(),
+                scattered_ray: None,
+            };
+        }
+
+        ScatterResult {
+            attenuation: self.ggx.g2(wo, wi) / self.ggx.g1(wo) * self.ior.fresnel(wo.dot(m)),
+            scattered_ray: Some(hit_record.spawn_ray(frame.vector_to_world(wi), ray_in.time)),
+        }
+    }
+
+    fn evaluate(
+        &self,
+        ray_in: &Ray,
+        hit_record: &HitRecord,
+        direction: Vec3,
+    ) -> Option<Evaluation> {
+        let (frame, wo) = Self::local(ray_in, hit_record);
+        let wi = frame.vector_to_local(direction.unit_vector());
+        if wi.z() <= 0.0 {
+            return Some(Evaluation {
+                value: Color::black(),
+                pdf: 0.0,
+            });
+        }
+
+        // the cosine at the surface cancels with the one in the BRDF
+        let m = (wo + wi).unit_vector();
+        let d = self.ggx.d(m);
+        Some(Evaluation {
+            value: d * self.ggx.g2(wo, wi) / (4.0 * wo.z()) * self.ior.fresnel(wo.dot(m)),
+            pdf: self.ggx.visible_normal_pdf(wo, m) / (4.0 * wo.dot(m)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ComplexIor, Conductor};
+    use crate::{
+        geom::{Point3, Ray, Vec3},
+        hittable::HitRecord,
+        material::Material,
+    };
+    use std::sync::Arc;
+
+    #[test]
+    fn test_evaluate_matches_scatter() {
+        let gold = Arc::new(Conductor::new(ComplexIor::gold(), 0.4, 0.5));
+        for x in [0.0, 0.5, 2.0] {
+            let ray = Ray::new(Point3::new(-x, 1.0, 0.3), Vec3::new(x, -1.0, -0.3), 0.0);
+            let hit = HitRecord::new(
+                Point3::default(),
+                1.0,
+                ray,
+                Vec3::new(0.0, 1.0, 0.0),
+                gold.clone(),
+                0.0,
+                0.0,
+            );
+            // shadowing and Fresnel can only lose light, never add it
+            for _ in 0..1000 {
+                let scattered = gold.scatter(&ray, &hit);
+                let attenuation = scattered.attenuation;
+                assert!(attenuation.r() <= 1.0 && attenuation.g() <= 1.0);
+                assert!(attenuation.b() <= 1.0 && attenuation.b() >= 0.0);
+                let Some(scattered_ray) = scattered.scattered_ray else {
+                    continue;
+                };
+                assert!(scattered_ray.direction.dot(hit.normal) > 0.0);
+
+                // sampling must weight each direction by exactly the BRDF
+                // over the density it picks it with
+                let evaluation = gold.evaluate(&ray, &hit, scattered_ray.direction).unwrap();
+                let weight = evaluation.value / evaluation.pdf;
+                assert!((weight - attenuation).length() < 1e-9);
+            }
+        }
+
+        // head on, gold reflects most red light and least blue
+        let reflectance = ComplexIor::gold().fresnel(1.0);
+        assert!(reflectance.r() > 0.9 && reflectance.b() < 0.5);
+    }
+}
@@ -1,13 +1,32 @@
 use rand::Rng;
 
 use crate::{
-    geom::{Color, Ray},
+    geom::{Color, Frame, Ray, Vec3},
     hittable::HitRecord,
-    material::{Material, ScatterResult},
+    material::{
+        microfacet::{dielectric_schlick, sample_dielectric, Ggx},
+        Interior, Material, ScatterResult,
+    },
 };
 
+/// Views more grazing than this are treated as this, since shading normals
+/// can face slightly away from the ray.
+const MIN_COSINE: f64 = 1e-4;
+
+/// Colors are clamped to at least this much light getting through, to keep
+/// absorption finite.
+const MIN_TRANSMITTANCE: f64 = 1e-6;
+
 pub struct Dielectric {
     pub index_of_refraction: f64,
+    /// How frosted the surface is, from 0 for smooth glass up to 1. Rough
+    /// surfaces reflect and refract off GGX microfacets.
+    pub roughness: f64,
+    /// How much light is absorbed per unit of distance inside, for each
+    /// channel, which tints thick glass and liquids.
+    pub absorption: Color,
+    /// Which of overlapping dielectrics fills the space they share.
+    pub priority: f64,
 }
 
 impl Material for Dielectric {
@@ -17,6 +36,18 @@ impl Material for Dielectric {
         } else {
             self.index_of_refraction
         };
+        self.scatter_across(ray_in, hit_record, refraction_ratio)
+    }
+
+    fn scatter_across(
+        &self,
+        ray_in: &Ray,
+        hit_record: &HitRecord,
+        refraction_ratio: f64,
+    ) -> ScatterResult {
+        if self.roughness > 0.0 {
+            return self.scatter_rough(ray_in, hit_record, refraction_ratio);
+        }
 
         let unit_direction = ray_in.direction.unit_vector();
         let cos_theta = (-unit_direction).dot(hit_record.normal).min(1.0);
@@ -24,7 +55,7 @@ impl Material for Dielectric {
 
         let cannot_refract = refraction_ratio * sin_theta > 1.0;
         let mut rng = rand::thread_rng();
-        let should_reflect = Self::reflectance(cos_theta, refraction_ratio) > rng.gen::<f64>();
+        let should_reflect = dielectric_schlick(cos_theta, refraction_ratio) > rng.gen::<f64>();
         let direction = if cannot_refract || should_reflect {
             unit_direction.reflect(hit_record.normal)
         } else {
@@ -33,15 +64,72 @@ impl Material for Dielectric {
 
         ScatterResult {
             attenuation: Color::white(),
-            scattered_ray: Some(Ray::new(hit_record.p, direction, ray_in.time)),
+            scattered_ray: Some(hit_record.spawn_ray(direction, ray_in.time)),
         }
     }
+
+    fn interior(&self) -> Option<Interior> {
+        Some(Interior {
+            absorption: self.absorption,
+            index_of_refraction: self.index_of_refraction,
+            priority: self.priority,
+        })
+    }
 }
 
 impl Dielectric {
-    fn reflectance(cosine: f64, ref_idx: f64) -> f64 {
-        // Schlick's approximation
-        let r0 = ((1.0 - ref_idx) / (1.0 + ref_idx)).powi(2);
-        r0 + (1.0 - r0) * (1.0 - cosine).powi(5)
+    /// The absorption that tints white light to `color` once it has traveled
+    /// `1 / density` through the dielectric.
+    pub fn absorption(color: Color, density: f64) -> Color {
+        let absorption = |c: f64| -c.clamp(MIN_TRANSMITTANCE, 1.0).ln() * density;
+        Color::new(
+            absorption(color.r()),
+            absorption(color.g()),
+            absorption(color.b()),
+        )
+    }
+
+    fn scatter_rough(
+        &self,
+        ray_in: &Ray,
+        hit_record: &HitRecord,
+        refraction_ratio: f64,
+    ) -> ScatterResult {
+        let frame = Frame::new(hit_record.p, hit_record.normal);
+        let wo = frame.vector_to_local(-ray_in.direction.unit_vector());
+        let wo = Vec3::new(wo.x(), wo.y(), wo.z().max(MIN_COSINE)).unit_vector();
+
+        let ggx = Ggx::from_roughness(self.roughness);
+        let sampled =
+            sample_dielectric(&ggx, wo, refraction_ratio.recip(), &mut rand::thread_rng());
+        match sampled {
+            Some((wi, throughput, _)) => ScatterResult {
+                attenuation: throughput * Color::white(),
+                scattered_ray: Some(hit_record.spawn_ray(frame.vector_to_world(wi), ray_in.time)),
+            },
+            None => ScatterResult {
+                attenuation: Color::black(),
+                scattered_ray: None,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Dielectric;
+    use crate::{geom::Color, material::Interior};
+
+    #[test]
+    fn test_absorption() {
+        let color = Color::new(0.4, 0.8, 1.0);
+        let interior = Interior {
+            absorption: Dielectric::absorption(color, 2.0),
+            index_of_refraction: 1.5,
+            priority: 0.0,
+        };
+        assert!((interior.transmittance(0.5) - color).length() < 1e-9);
+        // twice as far through is twice as tinted
+        assert!((interior.transmittance(1.0) - color * color).length() < 1e-9);
     }
 }
@@ -0,0 +1,98 @@
+use rand::Rng;
+use std::f64::consts::{FRAC_PI_2, PI};
+
+use crate::{
+    geom::{Color, Frame, Ray, Vec3},
+    hittable::HitRecord,
+    material::{microfacet::dielectric_schlick, Material, ScatterResult},
+    texture::Texture,
+};
+
+/// How far the cuticle scales on a hair tilt its highlights away from the
+/// mirror direction, in radians.
+const SCALE_TILT: f64 = 0.035;
+
+/// Light scattering from hair fibers, loosely following Marschner et al.
+/// Light either reflects off the surface of the fiber (R), passes through it
+/// (TT), or reflects once off the inside of the fiber (TRT). The two lobes
+/// that travel through the fiber are tinted by `color` each time they cross
+/// it.
+pub struct Hair {
+    pub color: Box<dyn Texture>,
+    /// How far the lobes spread out along the fiber, in radians.
+    pub roughness: f64,
+    pub index_of_refraction: f64,
+}
+
+impl Material for Hair {
+    fn scatter(&self, ray_in: &Ray, hit_record: &HitRecord) -> ScatterResult {
+        let mut rng = rand::thread_rng();
+
+        // anything that isn't a curve is treated as a fiber running across
+        // the surface
+        let tangent = hit_record.tangent.unwrap_or_else(|| {
+            Frame::new(hit_record.p, hit_record.normal).vector_to_world(Vec3::new(1.0, 0.0, 0.0))
+        });
+        let tangent = tangent.unit_vector();
+        let normal = (hit_record.normal - hit_record.normal.project(tangent)).unit_vector();
+
+        let incoming = ray_in.direction.unit_vector();
+        let sin_theta = incoming.dot(tangent).clamp(-1.0, 1.0);
+        let theta = sin_theta.asin();
+        let across = incoming - incoming.project(tangent);
+        let across = if across.is_near_zero() {
+            -normal
+        } else {
+            across.unit_vector()
+        };
+
+        // how squarely the ray hits the fiber, both along it and around it
+        let cos_incidence = theta.cos() * (-across).dot(normal).clamp(0.0, 1.0);
+        let f = dielectric_schlick(cos_incidence, self.index_of_refraction);
+
+        let color = self.color.value(hit_record.u, hit_record.v, hit_record.p);
+        let weights = [f, (1.0 - f).powi(2), (1.0 - f).powi(2) * f];
+        let total: f64 = weights.iter().sum();
+        let choice = rng.gen::<f64>() * total;
+
+        let (attenuation, tilt, spread, out_across) = if choice < weights[0] {
+            let reflected = across.reflect(normal);
+            (Color::white(), -2.0 * SCALE_TILT, self.roughness, reflected)
+        } else if choice < weights[0] + weights[1] {
+            (color, SCALE_TILT, 0.5 * self.roughness, across)
+        } else {
+            let turn = rng.gen_range(-PI..PI);
+            let (sin_turn, cos_turn) = turn.sin_cos();
+            let around = cos_turn * normal + sin_turn * tangent.cross(normal);
+            (
+                color * color,
+                4.0 * SCALE_TILT,
+                2.0 * self.roughness,
+                around,
+            )
+        };
+
+        // spread out around the fiber a little too, so that the lobes aren't
+        // perfectly sharp
+        let turn = spread * gaussian(&mut rng);
+        let (sin_turn, cos_turn) = turn.sin_cos();
+        let out_across = cos_turn * out_across + sin_turn * tangent.cross(out_across);
+
+        let theta_out =
+            (theta + tilt + spread * gaussian(&mut rng)).clamp(-FRAC_PI_2 + 1e-3, FRAC_PI_2 - 1e-3);
+        let direction = theta_out.sin() * tangent + theta_out.cos() * out_across;
+
+        ScatterResult {
+            attenuation: total * attenuation,
+            scattered_ray: Some(hit_record.spawn_ray(direction, ray_in.time)),
+        }
+    }
+}
+
+/// A sample from the standard normal distribution, by the Box-Muller
+/// transform.
+fn gaussian(rng: &mut impl Rng) -> f64 {
+    let a: f64 = 1.0 - rng.gen::<f64>();
+    let b: f64 = rng.gen();
+    (-2.0 * a.ln()).sqrt() * (2.0 * PI * b).cos()
+}
@@ -0,0 +1,132 @@
+use std::sync::Arc;
+
+use crate::{geom::Color, material::Material};
+
+/// What fills the inside of a closed object, which absorbs light as it
+/// travels through.
+#[derive(Clone, Copy)]
+pub struct Interior {
+    /// How much light is absorbed per unit of distance, for each channel.
+    pub absorption: Color,
+    pub index_of_refraction: f64,
+    /// Where objects overlap, the one with the highest priority fills the
+    /// overlap, and the surfaces of the others inside it are ignored.
+    pub priority: f64,
+}
+
+impl Interior {
+    /// How much light is left after traveling `distance` through the
+    /// interior, by the Beer-Lambert law.
+    pub fn transmittance(&self, distance: f64) -> Color {
+        let optical_depth = -distance * self.absorption;
+        Color::new(
+            optical_depth.r().exp(),
+            optical_depth.g().exp(),
+            optical_depth.b().exp(),
+        )
+    }
+}
+
+/// The interiors a ray is inside, in the order it entered them, with the
+/// material each belongs to. This lets objects nest and overlap, like ice in
+/// water in a glass, following Schmidt and Budge's "Simple Nested
+/// Dielectrics in Ray Traced Images": the ray is in whichever medium has the
+/// highest priority, and surfaces are only real where that changes.
+#[derive(Clone, Default)]
+pub struct Media(Vec<(Arc<dyn Material>, Interior)>);
+
+impl Media {
+    /// The medium the ray is in, or `None` in open air. Ties go to the most
+    /// recently entered.
+    pub fn current(&self) -> Option<Interior> {
+        self.current_entry().map(|(_, interior)| *interior)
+    }
+
+    fn current_entry(&self) -> Option<&(Arc<dyn Material>, Interior)> {
+        self.0
+            .iter()
+            .max_by(|(_, a), (_, b)| a.priority.total_cmp(&b.priority))
+    }
+
+    fn index_of_refraction(&self) -> f64 {
+        self.current()
+            .map_or(1.0, |interior| interior.index_of_refraction)
+    }
+
+    /// Crosses a surface of `material`, into the object it encloses if
+    /// `entering`, or else out of it. Returns the media on the far side, and
+    /// the ratio of the index of refraction on the near side to the far side,
+    /// or `None` if the ray stays in the same medium, so the surface isn't
+    /// really there.
+    pub fn cross(
+        &self,
+        material: &Arc<dyn Material>,
+        interior: Interior,
+        entering: bool,
+    ) -> (Self, Option<f64>) {
+        let near = self.index_of_refraction();
+        let mut beyond = self.clone();
+        if entering {
+            beyond.0.push((material.clone(), interior));
+        } else {
+            let same_material = |(m, _): &(Arc<dyn Material>, Interior)| {
+                std::ptr::addr_eq(Arc::as_ptr(m), Arc::as_ptr(material))
+            };
+            let Some(index) = self.0.iter().rposition(same_material) else {
+                // leaving an object the ray never entered, such as one the
+                // camera is inside, so all that's known is its own interior
+                return (beyond, Some(interior.index_of_refraction / near));
+            };
+            beyond.0.remove(index);
+        }
+
+        let same_medium = match (self.current_entry(), beyond.current_entry()) {
+            (None, None) => true,
+            (Some((a, _)), Some((b, _))) => std::ptr::addr_eq(Arc::as_ptr(a), Arc::as_ptr(b)),
+            _ => false,
+        };
+        let refraction_ratio = near / beyond.index_of_refraction();
+        (beyond, (!same_medium).then_some(refraction_ratio))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Media;
+    use crate::{
+        geom::Color,
+        material::{Dielectric, Material},
+    };
+    use std::sync::Arc;
+
+    #[test]
+    fn test_cross() {
+        let medium = |index_of_refraction, priority| -> Arc<dyn Material> {
+            Arc::new(Dielectric {
+                index_of_refraction,
+                roughness: 0.0,
+                absorption: Color::black(),
+                priority,
+            })
+        };
+        let glass = medium(1.5, 1.0);
+        let water = medium(1.33, 2.0);
+        let ratio = |(_, ratio): &(Media, Option<f64>)| ratio.map(|r| (r * 100.0).round() / 100.0);
+
+        // into the glass from the air, then into the water, which wins where
+        // they overlap
+        let in_glass = Media::default().cross(&glass, glass.interior().unwrap(), true);
+        assert_eq!(ratio(&in_glass), Some(0.67));
+        let in_water = in_glass.0.cross(&water, water.interior().unwrap(), true);
+        assert_eq!(ratio(&in_water), Some(1.13));
+
+        // the glass's inner wall, inside the water, isn't really there
+        let past_wall = in_water.0.cross(&glass, glass.interior().unwrap(), false);
+        assert_eq!(ratio(&past_wall), None);
+
+        // and leaving the water is straight back out to the air
+        let out = past_wall.0.cross(&water, water.interior().unwrap(), false);
+        assert_eq!(ratio(&out), Some(1.33));
+        assert!(out.0.current().is_none());
+    }
+}
@@ -20,7 +20,7 @@ impl Material for Lambertian {
 
         ScatterResult {
             attenuation: self.albedo.value(hit_record.u, hit_record.v, hit_record.p),
-            scattered_ray: Some(Ray::new(hit_record.p, scatter_direction, ray_in.time)),
+            scattered_ray: Some(hit_record.spawn_ray(scatter_direction, ray_in.time)),
         }
     }
 }
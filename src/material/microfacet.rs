@@ -0,0 +1,196 @@
+use rand::Rng;
+use std::f64::consts::PI;
+
+use crate::geom::{Color, Vec3};
+
+/// Below this, GGX roughness is treated as this, which is smooth enough to
+/// look like a mirror without the distribution becoming degenerate.
+const MIN_ALPHA: f64 = 1e-4;
+
+/// The GGX (Trowbridge-Reitz) distribution of microfacet normals, for rough
+/// surfaces. Directions are in a local frame where the surface normal is Z,
+/// and point away from the surface.
+pub(super) struct Ggx {
+    alpha_x: f64,
+    alpha_y: f64,
+}
+
+impl Ggx {
+    pub(super) fn isotropic(alpha: f64) -> Self {
+        let alpha = alpha.max(MIN_ALPHA);
+        Self {
+            alpha_x: alpha,
+            alpha_y: alpha,
+        }
+    }
+
+    /// Stretched to `alpha_x` along the local X axis, and `alpha_y` along Y.
+    pub(super) fn anisotropic(alpha_x: f64, alpha_y: f64) -> Self {
+        Self {
+            alpha_x: alpha_x.max(MIN_ALPHA),
+            alpha_y: alpha_y.max(MIN_ALPHA),
+        }
+    }
+
+    /// Uses the square of `roughness` as alpha, which makes roughness look
+    /// perceptually linear.
+    pub(super) fn from_roughness(roughness: f64) -> Self {
+        Self::isotropic(roughness.powi(2))
+    }
+
+    /// The density of microfacets with normal `m`, per unit of solid angle
+    /// and of projected area.
+    pub(super) fn d(&self, m: Vec3) -> f64 {
+        let e = (m.x() / self.alpha_x).powi(2) + (m.y() / self.alpha_y).powi(2) + m.z().powi(2);
+        (PI * self.alpha_x * self.alpha_y * e.powi(2)).recip()
+    }
+
+    /// Smith's auxiliary function, for how much of the surface facing `w` is
+    /// hidden by other microfacets.
+    fn lambda(&self, w: Vec3) -> f64 {
+        let tan2 =
+            ((self.alpha_x * w.x()).powi(2) + (self.alpha_y * w.y()).powi(2)) / w.z().powi(2);
+        0.5 * ((1.0 + tan2).sqrt() - 1.0)
+    }
+
+    /// The fraction of microfacets facing `w` that are visible from it.
+    pub(super) fn g1(&self, w: Vec3) -> f64 {
+        (1.0 + self.lambda(w)).recip()
+    }
+
+    /// The fraction of microfacets that are visible from both directions,
+    /// accounting for the correlation between them.
+    pub(super) fn g2(&self, wo: Vec3, wi: Vec3) -> f64 {
+        (1.0 + self.lambda(wo) + self.lambda(wi)).recip()
+    }
+
+    /// The density `sample_visible_normal` picks `m` with.
+    pub(super) fn visible_normal_pdf(&self, wo: Vec3, m: Vec3) -> f64 {
+        self.g1(wo) * wo.dot(m).max(0.0) * self.d(m) / wo.z()
+    }
+
+    /// Samples a microfacet normal in proportion to how much of it is seen
+    /// from `wo`, following Heitz's "Sampling the GGX Distribution of Visible
+    /// Normals". Reflecting off a normal sampled this way has a throughput
+    /// of just `F * g2 / g1(wo)`.
+    pub(super) fn sample_visible_normal(&self, wo: Vec3, rng: &mut impl Rng) -> Vec3 {
+        // stretch the view direction to where the distribution is a
+        // hemisphere
+        let vh = Vec3::new(self.alpha_x * wo.x(), self.alpha_y * wo.y(), wo.z()).unit_vector();
+        let length_squared = vh.x().powi(2) + vh.y().powi(2);
+        let t1 = if length_squared > 0.0 {
+            Vec3::new(-vh.y(), vh.x(), 0.0) / length_squared.sqrt()
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let t2 = vh.cross(t1);
+
+        // a point on the projected half disk
+        let r = rng.gen::<f64>().sqrt();
+        let phi = 2.0 * PI * rng.gen::<f64>();
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1.0 + vh.z());
+        let p2 = (1.0 - s) * (1.0 - p1.powi(2)).sqrt() + s * r * phi.sin();
+
+        // back onto the hemisphere, then unstretch
+        let nh = p1 * t1 + p2 * t2 + (1.0 - p1.powi(2) - p2.powi(2)).max(0.0).sqrt() * vh;
+        Vec3::new(
+            self.alpha_x * nh.x(),
+            self.alpha_y * nh.y(),
+            nh.z().max(1e-6),
+        )
+        .unit_vector()
+    }
+}
+
+/// Schlick's approximation of how much light reflects at an angle, given how
+/// much reflects head on.
+pub(super) fn schlick(f0: Color, cosine: f64) -> Color {
+    f0 + (Color::white() - f0) * (1.0 - cosine).clamp(0.0, 1.0).powi(5)
+}
+
+/// Schlick's approximation of `dielectric_fresnel`. It only depends on
+/// `eta` through how much light reflects head on, which is the same either
+/// way round.
+pub(super) fn dielectric_schlick(cos_i: f64, eta: f64) -> f64 {
+    let r0 = ((1.0 - eta) / (1.0 + eta)).powi(2);
+    r0 + (1.0 - r0) * (1.0 - cos_i).powi(5)
+}
+
+/// How much unpolarized light reflects off a dielectric, where `eta` is the
+/// ratio of the index of refraction on the far side to the near side.
+pub(super) fn dielectric_fresnel(cos_i: f64, eta: f64) -> f64 {
+    let sin2_t = (1.0 - cos_i.powi(2)) / eta.powi(2);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let rs = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    let rp = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    0.5 * (rs.powi(2) + rp.powi(2))
+}
+
+/// Scatters off a rough dielectric interface, where `eta` is the ratio of the
+/// index of refraction on the far side to the near side. A visible normal is
+/// sampled, and then the ray either reflects off it or refracts through it,
+/// in proportion to the Fresnel term.
+///
+/// Returns the new direction, its throughput, and whether it went through
+/// the surface. Directions that end up on the wrong side of the surface are
+/// absorbed.
+pub(super) fn sample_dielectric(
+    ggx: &Ggx,
+    wo: Vec3,
+    eta: f64,
+    rng: &mut impl Rng,
+) -> Option<(Vec3, f64, bool)> {
+    let m = ggx.sample_visible_normal(wo, rng);
+    let cos_o = wo.dot(m);
+    let fresnel = dielectric_fresnel(cos_o, eta);
+
+    let (wi, refracted) = if rng.gen::<f64>() < fresnel {
+        (2.0 * cos_o * m - wo, false)
+    } else {
+        let cos_t = (1.0 - (1.0 - cos_o.powi(2)) / eta.powi(2)).sqrt();
+        ((cos_o / eta - cos_t) * m - wo / eta, true)
+    };
+    let height = if refracted { -wi.z() } else { wi.z() };
+    if height <= 0.0 {
+        return None;
+    }
+    Some((wi, ggx.g2(wo, wi) / ggx.g1(wo), refracted))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{sample_dielectric, Ggx};
+    use crate::geom::Vec3;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    #[test]
+    fn test_sample_dielectric() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let wo = Vec3::new(0.6, 0.0, 0.8);
+
+        // a smooth interface follows Snell's law, and loses no light
+        let smooth = Ggx::from_roughness(0.0);
+        for _ in 0..100 {
+            let (wi, throughput, refracted) =
+                sample_dielectric(&smooth, wo, 1.5, &mut rng).unwrap();
+            assert!((throughput - 1.0).abs() < 1e-3);
+            if refracted {
+                assert!((wi.x() * 1.5 + wo.x()).abs() < 1e-3);
+            } else {
+                assert!((wi - Vec3::new(-0.6, 0.0, 0.8)).length() < 1e-3);
+            }
+        }
+
+        // rough ones can lose light to shadowing, but never gain any
+        let rough = Ggx::from_roughness(0.7);
+        for _ in 0..1000 {
+            if let Some((_, throughput, _)) = sample_dielectric(&rough, wo, 1.5, &mut rng) {
+                assert!(throughput > 0.0 && throughput <= 1.0);
+            }
+        }
+    }
+}
@@ -1,15 +1,24 @@
+mod conductor;
 mod dielectric;
 mod emissive;
+mod hair;
+mod interior;
 mod lambertian;
 mod metal;
+mod microfacet;
+mod principled;
 
+pub use conductor::{ComplexIor, Conductor};
 pub use dielectric::Dielectric;
 pub use emissive::DiffuseLight;
+pub use hair::Hair;
+pub use interior::{Interior, Media};
 pub use lambertian::Lambertian;
 pub use metal::Metal;
+pub use principled::Principled;
 
 use crate::{
-    geom::{Color, Point3, Ray},
+    geom::{Color, Point3, Ray, Vec3},
     hittable::HitRecord,
 };
 
@@ -21,6 +30,39 @@ pub trait Material: Send + Sync {
         }
     }
 
+    /// For sampling lights directly: how much of the light arriving from
+    /// `direction` the surface sends back along the incoming ray, including
+    /// the cosine at the surface, and the density per unit of solid angle
+    /// that `scatter` picks `direction` with. Materials that only scatter in
+    /// exact directions, like mirrors, can't be evaluated.
+    fn evaluate(
+        &self,
+        _ray_in: &Ray,
+        _hit_record: &HitRecord,
+        _direction: Vec3,
+    ) -> Option<Evaluation> {
+        None
+    }
+
+    /// Like `scatter`, for materials with an interior, at a surface between
+    /// two media whose indices of refraction have the ratio
+    /// `refraction_ratio`, near side over far side, rather than between the
+    /// material and air.
+    fn scatter_across(
+        &self,
+        ray_in: &Ray,
+        hit_record: &HitRecord,
+        _refraction_ratio: f64,
+    ) -> ScatterResult {
+        self.scatter(ray_in, hit_record)
+    }
+
+    /// What fills closed objects made of this material, for materials that
+    /// light can pass into.
+    fn interior(&self) -> Option<Interior> {
+        None
+    }
+
     fn emitted(&self, _u: f64, _v: f64, _p: Point3) -> Color {
         Color::black()
     }
@@ -30,3 +72,8 @@ pub struct ScatterResult {
     pub attenuation: Color,
     pub scattered_ray: Option<Ray>,
 }
+
+pub struct Evaluation {
+    pub value: Color,
+    pub pdf: f64,
+}
@@ -0,0 +1,260 @@
+use rand::{
+    distributions::{Distribution, WeightedIndex},
+    Rng,
+};
+use std::f64::consts::PI;
+
+use crate::{
+    geom::{Color, Frame, Ray, Vec3},
+    hittable::HitRecord,
+    material::{
+        microfacet::{sample_dielectric, schlick, Ggx},
+        Material, ScatterResult,
+    },
+    texture::Texture,
+};
+
+/// Views more grazing than this are treated as this, since shading normals
+/// can face slightly away from the ray.
+const MIN_COSINE: f64 = 1e-4;
+
+/// A single material that covers plastics, metals, glass, varnished wood,
+/// cloth and so on, following the Disney "principled" BSDF (Burley 2012 and
+/// 2015). Apart from the base color, every parameter is from 0 to 1, and
+/// reads its value from the average of its texture's channels.
+///
+/// Each bounce picks one of its lobes (diffuse, specular, glass or
+/// clearcoat) at random, roughly in proportion to how much light it
+/// reflects, and weights the result to make up for the others.
+pub struct Principled {
+    pub base_color: Box<dyn Texture>,
+    /// Blends from a dielectric to a metal, which has no diffuse lobe and
+    /// reflections tinted by the base color.
+    pub metallic: Box<dyn Texture>,
+    pub roughness: Box<dyn Texture>,
+    /// How strongly dielectrics reflect head on, where 0.5 is a typical 4%.
+    pub specular: Box<dyn Texture>,
+    /// Blends from an opaque surface to glass tinted by the base color.
+    pub transmission: Box<dyn Texture>,
+    /// The strength of a second, colorless, specular layer on top.
+    pub clearcoat: Box<dyn Texture>,
+    /// How smooth the clearcoat is.
+    pub clearcoat_gloss: Box<dyn Texture>,
+    /// A soft highlight at grazing angles, as on cloth.
+    pub sheen: Box<dyn Texture>,
+    /// Flattens the diffuse lobe, as if light scattered beneath the surface.
+    pub subsurface: Box<dyn Texture>,
+    /// Used by the glass lobe.
+    pub index_of_refraction: f64,
+}
+
+impl Material for Principled {
+    fn scatter(&self, ray_in: &Ray, hit_record: &HitRecord) -> ScatterResult {
+        let mut rng = rand::thread_rng();
+        let (u, v, p) = (hit_record.u, hit_record.v, hit_record.p);
+        let scalar = |texture: &dyn Texture| average(texture.value(u, v, p)).clamp(0.0, 1.0);
+        let base_color = self.base_color.value(u, v, p);
+        let metallic = scalar(self.metallic.as_ref());
+        let roughness = scalar(self.roughness.as_ref());
+        let transmission = scalar(self.transmission.as_ref());
+        let clearcoat = scalar(self.clearcoat.as_ref());
+        let sheen = scalar(self.sheen.as_ref());
+        let subsurface = scalar(self.subsurface.as_ref());
+
+        let frame = Frame::new(p, hit_record.normal);
+        let wo = frame.vector_to_local(-ray_in.direction.unit_vector());
+        let wo = Vec3::new(wo.x(), wo.y(), wo.z().max(MIN_COSINE)).unit_vector();
+
+        let dielectric_f0 = 0.08 * scalar(self.specular.as_ref()) * Color::white();
+        let specular_f0 = (1.0 - metallic) * dielectric_f0 + metallic * base_color;
+        let clearcoat_f0 = Color::new(0.04, 0.04, 0.04);
+
+        // how much each lobe contributes, and a rough guess at how much light
+        // it reflects, to pick between them. The diffuse lobe only gets the
+        // light that the dielectric specular layer on top of it lets through.
+        let weights = [
+            (1.0 - metallic)
+                * (1.0 - transmission)
+                * (1.0 - average(schlick(dielectric_f0, wo.z()))),
+            1.0 - (1.0 - metallic) * transmission,
+            (1.0 - metallic) * transmission,
+            0.25 * clearcoat,
+        ];
+        let albedos = [
+            average(base_color) + sheen,
+            average(schlick(specular_f0, wo.z())),
+            1.0,
+            average(schlick(clearcoat_f0, wo.z())),
+        ];
+        let chances = [0, 1, 2, 3].map(|i| weights[i] * albedos[i]);
+        let Ok(lobes) = WeightedIndex::new(chances) else {
+            return ScatterResult {
+                attenuation: Color::black(),
+                scattered_ray: None,
+            };
+        };
+        let lobe = lobes.sample(&mut rng);
+        let chance = chances[lobe] / chances.iter().sum::<f64>();
+
+        let sampled = match lobe {
+            0 => Some(diffuse(wo, base_color, roughness, sheen, subsurface)),
+            1 => specular(&Ggx::from_roughness(roughness), wo, specular_f0, &mut rng),
+            2 => {
+                let eta = if hit_record.front_face {
+                    self.index_of_refraction
+                } else {
+                    self.index_of_refraction.recip()
+                };
+                sample_dielectric(&Ggx::from_roughness(roughness), wo, eta, &mut rng).map(
+                    |(wi, throughput, refracted)| {
+                        let tint = if refracted {
+                            base_color
+                        } else {
+                            Color::white()
+                        };
+                        (wi, throughput * tint)
+                    },
+                )
+            }
+            _ => {
+                let gloss = scalar(self.clearcoat_gloss.as_ref());
+                let ggx = Ggx::isotropic(0.1 + (0.001 - 0.1) * gloss);
+                specular(&ggx, wo, clearcoat_f0, &mut rng)
+            }
+        };
+
+        match sampled {
+            Some((wi, throughput)) => ScatterResult {
+                attenuation: weights[lobe] / chance * throughput,
+                scattered_ray: Some(hit_record.spawn_ray(frame.vector_to_world(wi), ray_in.time)),
+            },
+            None => ScatterResult {
+                attenuation: Color::black(),
+                scattered_ray: None,
+            },
+        }
+    }
+}
+
+/// Samples the diffuse lobe, which is brighter at grazing angles on rough
+/// surfaces and darker on smooth ones, with the subsurface approximation
+/// and sheen mixed in.
+fn diffuse(
+    wo: Vec3,
+    base_color: Color,
+    roughness: f64,
+    sheen: f64,
+    subsurface: f64,
+) -> (Vec3, Color) {
+    let mut wi = Vec3::new(0.0, 0.0, 1.0) + Vec3::rand_unit_vector();
+    if wi.is_near_zero() {
+        wi = Vec3::new(0.0, 0.0, 1.0);
+    }
+    let wi = wi.unit_vector();
+
+    let half = (wi + wo).unit_vector();
+    let cos_d = wi.dot(half);
+    let fresnel_i = schlick_weight(wi.z());
+    let fresnel_o = schlick_weight(wo.z());
+
+    // Burley's diffuse reflects more than it receives on rough surfaces, so
+    // it is renormalized as in Frostbite (Lagarde and de Rousiers 2014)
+    let fd90 = 0.5 * roughness + 2.0 * roughness * cos_d.powi(2);
+    let energy_factor = 1.0 + (1.51_f64.recip() - 1.0) * roughness;
+    let fd = (1.0 + (fd90 - 1.0) * fresnel_i) * (1.0 + (fd90 - 1.0) * fresnel_o) * energy_factor;
+    let fss90 = roughness * cos_d.powi(2);
+    let fss = (1.0 + (fss90 - 1.0) * fresnel_i) * (1.0 + (fss90 - 1.0) * fresnel_o);
+    // without Burley's 1.25 scale, which makes the flattened lobe reflect
+    // more light than it receives at grazing angles
+    let ss = fss * ((wi.z() + wo.z()).recip() - 0.5) + 0.5;
+
+    // the sheen is tinted halfway towards the hue of the base color
+    let luminance = base_color.dot(Color::new(0.2126, 0.7152, 0.0722));
+    let tint = if luminance > 0.0 {
+        base_color / luminance
+    } else {
+        Color::white()
+    };
+    let sheen_color = 0.5 * (Color::white() + tint);
+
+    // cosine sampling cancels the cosine and the 1/pi of the BRDF, and
+    // the sheen has no 1/pi to cancel
+    let throughput = ((1.0 - subsurface) * fd + subsurface * ss) * base_color
+        + PI * sheen * schlick_weight(cos_d) * sheen_color;
+    (wi, throughput)
+}
+
+/// Samples a GGX reflection lobe with Schlick's Fresnel term.
+fn specular(ggx: &Ggx, wo: Vec3, f0: Color, rng: &mut impl Rng) -> Option<(Vec3, Color)> {
+    let m = ggx.sample_visible_normal(wo, rng);
+    let wi = 2.0 * wo.dot(m) * m - wo;
+    (wi.z() > 0.0).then(|| (wi, ggx.g2(wo, wi) / ggx.g1(wo) * schlick(f0, wo.dot(m))))
+}
+
+fn schlick_weight(cosine: f64) -> f64 {
+    (1.0 - cosine).clamp(0.0, 1.0).powi(5)
+}
+
+fn average(color: Color) -> f64 {
+    (color.r() + color.g() + color.b()) / 3.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Principled;
+    use crate::{
+        geom::{Color, Point3, Ray, Vec3},
+        hittable::HitRecord,
+        material::Material,
+        texture::{SolidColor, Texture},
+    };
+    use std::sync::Arc;
+
+    /// A white surface that doesn't absorb or transmit any light can reflect
+    /// all of it at most, from any direction.
+    #[test]
+    fn test_furnace() {
+        let solid = |value: f64| -> Box<dyn Texture> {
+            Box::new(SolidColor(Color::new(value, value, value)))
+        };
+        for roughness in [0.0, 0.25, 0.5, 0.75, 1.0] {
+            for subsurface in [0.0, 1.0] {
+                let material = Arc::new(Principled {
+                    base_color: solid(1.0),
+                    metallic: solid(0.0),
+                    roughness: solid(roughness),
+                    specular: solid(0.5),
+                    transmission: solid(0.0),
+                    clearcoat: solid(0.0),
+                    clearcoat_gloss: solid(0.0),
+                    sheen: solid(0.0),
+                    subsurface: solid(subsurface),
+                    index_of_refraction: 1.5,
+                });
+                // from head on to grazing
+                for x in [0.0, 1.0, 3.0, 10.0, 100.0] {
+                    let ray = Ray::new(Point3::new(-x, 1.0, 0.0), Vec3::new(x, -1.0, 0.0), 0.0);
+                    let hit = HitRecord::new(
+                        Point3::default(),
+                        1.0,
+                        ray,
+                        Vec3::new(0.0, 1.0, 0.0),
+                        material.clone(),
+                        0.0,
+                        0.0,
+                    );
+                    let samples = 10_000;
+                    let total = (0..samples)
+                        .map(|_| material.scatter(&ray, &hit).attenuation)
+                        .fold(Color::black(), |a, b| a + b);
+                    let albedo = total / f64::from(samples);
+                    assert!(
+                        albedo.r() <= 1.0,
+                        "reflects {albedo:?} with roughness {roughness}, subsurface {subsurface}, looking along {:?}",
+                        ray.direction
+                    );
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,163 @@
+//! Closed form solvers for low order polynomials, used by the analytic
+//! primitives. Each solver returns the real roots in no particular order.
+
+/// How small a value can be, relative to the terms it was computed from,
+/// before it is treated as zero. Tolerances are relative so that the
+/// solvers work the same whatever the scale of the coefficients.
+const EPSILON: f64 = 1e-9;
+
+/// Whether `x` is zero up to rounding, given the magnitudes of the terms it
+/// was computed from.
+fn is_negligible(x: f64, terms: &[f64]) -> bool {
+    let scale = terms
+        .iter()
+        .fold(0.0_f64, |scale, term| scale.max(term.abs()));
+    x.abs() <= EPSILON * scale
+}
+
+/// Solves `a*x^2 + b*x + c = 0`.
+pub fn solve_quadratic(a: f64, b: f64, c: f64) -> Vec<f64> {
+    // however small a is, the roots are found accurately below, with the
+    // second one heading off to infinity, so only an exact zero is linear
+    if a == 0.0 {
+        return if b == 0.0 { vec![] } else { vec![-c / b] };
+    }
+    let discrim = b * b - 4.0 * a * c;
+    if discrim < 0.0 {
+        vec![]
+    } else {
+        // avoid cancellation by computing the larger magnitude root first
+        let q = -0.5 * (b + b.signum() * discrim.sqrt());
+        if q == 0.0 {
+            vec![0.0]
+        } else {
+            vec![q / a, c / q]
+        }
+    }
+}
+
+/// Solves `x^3 + a*x^2 + b*x + c = 0`.
+fn solve_normed_cubic(a: f64, b: f64, c: f64) -> Vec<f64> {
+    // substitute x = y - a/3 to eliminate the quadratic term
+    let sq_a = a * a;
+    let p = (-sq_a / 3.0 + b) / 3.0;
+    let q = 0.5 * (2.0 / 27.0 * a * sq_a - a * b / 3.0 + c);
+    let cb_p = p * p * p;
+    let d = q * q + cb_p;
+
+    let q_terms = [2.0 / 27.0 * a * sq_a, a * b / 3.0, c];
+    let roots = if is_negligible(d, &[q * q, cb_p]) {
+        if is_negligible(q, &q_terms) {
+            vec![0.0]
+        } else {
+            let u = (-q).cbrt();
+            vec![2.0 * u, -u]
+        }
+    } else if d < 0.0 {
+        let phi = (-q / (-cb_p).sqrt()).clamp(-1.0, 1.0).acos() / 3.0;
+        let t = 2.0 * (-p).sqrt();
+        let third = std::f64::consts::FRAC_PI_3;
+        vec![
+            t * phi.cos(),
+            -t * (phi + third).cos(),
+            -t * (phi - third).cos(),
+        ]
+    } else {
+        let sqrt_d = d.sqrt();
+        vec![(sqrt_d - q).cbrt() - (sqrt_d + q).cbrt()]
+    };
+
+    roots.into_iter().map(|y| y - a / 3.0).collect()
+}
+
+/// Solves `a*x^4 + b*x^3 + c*x^2 + d*x + e = 0` using Ferrari's method.
+pub fn solve_quartic(a: f64, b: f64, c: f64, d: f64, e: f64) -> Vec<f64> {
+    let (b, c, d, e) = (b / a, c / a, d / a, e / a);
+
+    // substitute x = y - b/4 to eliminate the cubic term
+    let sq_b = b * b;
+    let p = -3.0 / 8.0 * sq_b + c;
+    let q = sq_b * b / 8.0 - b * c / 2.0 + d;
+    let r = -3.0 / 256.0 * sq_b * sq_b + sq_b * c / 16.0 - b * d / 4.0 + e;
+
+    let r_terms = [3.0 / 256.0 * sq_b * sq_b, sq_b * c / 16.0, b * d / 4.0, e];
+    let mut roots = if is_negligible(r, &r_terms) {
+        let mut roots = solve_normed_cubic(0.0, p, q);
+        roots.push(0.0);
+        roots
+    } else {
+        // any root of the resolvent cubic splits the quartic into two quadratics
+        let z = solve_normed_cubic(-p / 2.0, -r, r * p / 2.0 - q * q / 8.0)[0];
+        let u = z * z - r;
+        let v = 2.0 * z - p;
+        let u = if is_negligible(u, &[z * z, r]) {
+            0.0
+        } else if u > 0.0 {
+            u.sqrt()
+        } else {
+            return vec![];
+        };
+        let v = if is_negligible(v, &[2.0 * z, p]) {
+            0.0
+        } else if v > 0.0 {
+            v.sqrt()
+        } else {
+            return vec![];
+        };
+        let v = if q < 0.0 { -v } else { v };
+
+        let mut roots = solve_quadratic(1.0, v, z - u);
+        roots.extend(solve_quadratic(1.0, -v, z + u));
+        roots
+    };
+
+    for root in &mut roots {
+        *root -= b / 4.0;
+        // polish with a couple of Newton iterations, since the closed form
+        // loses a lot of precision
+        for _ in 0..2 {
+            let f = (((*root + b) * *root + c) * *root + d) * *root + e;
+            let df = ((4.0 * *root + 3.0 * b) * *root + 2.0 * c) * *root + d;
+            if df != 0.0 {
+                *root -= f / df;
+            }
+        }
+    }
+    roots
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{solve_quadratic, solve_quartic};
+
+    fn sorted(mut roots: Vec<f64>) -> Vec<f64> {
+        roots.sort_by(f64::total_cmp);
+        roots
+    }
+
+    #[test]
+    fn test_quadratic() {
+        let roots = sorted(solve_quadratic(1.0, -3.0, 2.0));
+        assert_eq!(roots, vec![1.0, 2.0]);
+        assert!(solve_quadratic(1.0, 0.0, 1.0).is_empty());
+        // a root at zero doesn't hide the other one
+        assert_eq!(sorted(solve_quadratic(1.0, -2.0, 0.0)), vec![0.0, 2.0]);
+        // and a tiny leading coefficient, as from a ray that is short in a
+        // shape's own space, still gives both: (x - 1)(1e-12 x - 1)
+        let roots = sorted(solve_quadratic(1e-12, -(1.0 + 1e-12), 1.0));
+        assert!((roots[0] - 1.0).abs() < 1e-9);
+        assert!((roots[1] / 1e12 - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_quartic() {
+        // (x - 1)(x - 2)(x - 3)(x - 4)
+        let roots = sorted(solve_quartic(1.0, -10.0, 35.0, -50.0, 24.0));
+        assert_eq!(roots.len(), 4);
+        for (root, expected) in roots.iter().zip([1.0, 2.0, 3.0, 4.0]) {
+            assert!((root - expected).abs() < 1e-9, "{root} != {expected}");
+        }
+        // x^4 + 1 has no real roots
+        assert!(solve_quartic(1.0, 0.0, 0.0, 0.0, 1.0).is_empty());
+    }
+}
@@ -8,25 +8,113 @@ use rand::{thread_rng, Rng};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::path::PathBuf;
+use std::sync::Arc;
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename = "Scene")]
 pub(crate) struct SceneDesc {
     pub(crate) materials: HashMap<String, Material>,
+    /// Objects that are stored once, and placed any number of times with
+    /// `Hittable::Instance`.
+    #[serde(default)]
+    pub(crate) definitions: HashMap<String, Vec<Hittable>>,
     pub(crate) objects: Vec<Hittable>,
     pub(crate) camera: Camera,
     pub(crate) image: config::Image,
     pub(crate) background: Option<(Value, Value, Value)>,
+    /// A medium that fills the whole world.
+    pub(crate) atmosphere: Option<Atmosphere>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename = "Atmosphere")]
+pub(crate) struct Atmosphere {
+    /// Per unit of distance, for each color channel
+    pub(crate) absorption: Option<(Value, Value, Value)>,
+    /// Per unit of distance, for each color channel
+    pub(crate) scattering: Option<(Value, Value, Value)>,
+    /// Makes the atmosphere thin out above this height...
+    pub(crate) height: Option<Value>,
+    /// ...by a factor of `e` for every `scale_height` of climb.
+    pub(crate) scale_height: Option<Value>,
+    pub(crate) phase: Option<PhaseFunction>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub(crate) enum TextureDesc {
     Solid(f64, f64, f64),
+    /// The same value in every channel, for material parameters that are a
+    /// single number.
+    Gray(Value),
     Checkerboard(Box<TextureDesc>, Box<TextureDesc>),
     Perlin,
     Image(PathBuf),
 }
 
+/// Complex indices of refraction for `Material::Conductor`, either measured
+/// presets, or given per color channel.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) enum ComplexIor {
+    Gold,
+    Copper,
+    Aluminium,
+    Silver,
+    Custom {
+        eta: (Value, Value, Value),
+        k: (Value, Value, Value),
+    },
+}
+
+/// How media scatter light. Media are isotropic if they don't say otherwise.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) enum PhaseFunction {
+    Isotropic,
+    /// Positive `g` scatters forwards, and negative `g` backwards.
+    HenyeyGreenstein(Value),
+    /// A mix of two Henyey-Greenstein lobes, picking `second` with
+    /// probability `weight`.
+    TwoLobe {
+        first: Value,
+        second: Value,
+        weight: Value,
+    },
+}
+
+/// Light given off by a medium, which glows in proportion to how much light
+/// it absorbs. `volume` is the radiance of the glowing matter, tinted by
+/// `color`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename = "Emission")]
+pub(crate) struct Emission {
+    pub(crate) volume: VolumeDesc,
+    pub(crate) color: (Value, Value, Value),
+}
+
+/// Densities for `Hittable::HeterogeneousMedium`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) enum VolumeDesc {
+    /// Fractal noise, from empty up to `density`.
+    Noise {
+        density: Value,
+        frequency: Option<Value>,
+        octaves: Option<usize>,
+        threshold: Option<Value>,
+    },
+    /// Perlin turbulence, from empty up to `density`.
+    Turbulence {
+        density: Value,
+        frequency: Option<Value>,
+        octaves: Option<usize>,
+    },
+    /// Samples loaded from a file, spread over `bounds` (or the boundary's
+    /// bounding box). See [`crate::texture::VoxelGrid::load`] for the format.
+    Grid {
+        path: PathBuf,
+        scale: Option<Value>,
+        bounds: Option<Bounds>,
+    },
+}
+
 impl From<f64> for Value {
     fn from(v: f64) -> Self {
         Value::Number(v)
@@ -70,19 +158,172 @@ pub(crate) enum Hittable {
         range: Vec<i32>,
         object: Box<Hittable>,
     },
+    // The objects placed by transforms are held in an `Arc`, so that copies
+    // made by a `Pattern` can be recognized as the same object and share
+    // their geometry.
     Translate {
         offset: (Value, Value, Value),
-        hittable: Box<Hittable>,
+        hittable: Arc<Hittable>,
     },
     RotateY {
         angle: Value,
-        hittable: Box<Hittable>
+        hittable: Arc<Hittable>
     },
     ConstantMedium {
         boundary: Box<Hittable>,
         density: Value,
         texture: TextureDesc,
-    }
+        phase: Option<PhaseFunction>,
+    },
+    /// A medium whose density comes from `volume`, and which scatters light
+    /// with the color of `texture`.
+    HeterogeneousMedium {
+        boundary: Box<Hittable>,
+        volume: VolumeDesc,
+        texture: TextureDesc,
+        phase: Option<PhaseFunction>,
+        emission: Option<Box<Emission>>,
+    },
+    Cylinder {
+        base: (Value, Value, Value),
+        top: (Value, Value, Value),
+        radius: Value,
+        capped: Option<bool>,
+        material: Material,
+    },
+    Cone {
+        base: (Value, Value, Value),
+        top: (Value, Value, Value),
+        base_radius: Value,
+        top_radius: Option<Value>,
+        capped: Option<bool>,
+        material: Material,
+    },
+    Torus {
+        center: (Value, Value, Value),
+        axis: Option<(Value, Value, Value)>,
+        major_radius: Value,
+        minor_radius: Value,
+        material: Material,
+    },
+    Capsule {
+        base: (Value, Value, Value),
+        top: (Value, Value, Value),
+        radius: Value,
+        material: Material,
+    },
+    Csg {
+        op: CsgOp,
+        left: Box<Hittable>,
+        right: Box<Hittable>,
+    },
+    Sdf {
+        shape: SdfShape,
+        bounds: Option<Bounds>,
+        material: Material,
+    },
+    /// Places a copy of one of the scene's `definitions`. It is scaled, then
+    /// rotated around X, Y and Z (in degrees), then moved by `offset`.
+    Instance {
+        object: String,
+        offset: Option<(Value, Value, Value)>,
+        rotation: Option<(Value, Value, Value)>,
+        scale: Option<(Value, Value, Value)>,
+    },
+    /// Moves an object during the camera's shutter time. The keyframes'
+    /// scale, rotation and offset work like `Instance`, and are interpolated
+    /// linearly in between.
+    Animated {
+        keyframes: Vec<Keyframe>,
+        hittable: Arc<Hittable>,
+    },
+    Heightfield {
+        image: PathBuf,
+        center: Option<(Value, Value, Value)>,
+        size: (Value, Value),
+        height: Value,
+        material: Material,
+    },
+    /// Strands of hair, fur or grass, loaded from a text file. See
+    /// [`crate::hittable::Curves::load`] for the format.
+    Curves {
+        strands: PathBuf,
+        shape: Option<CurveShape>,
+        material: Material,
+    },
+    /// Lots of spheres, such as particles from a simulation, loaded from a
+    /// file. See [`crate::hittable::SphereSet::load`] for the formats. Each
+    /// particle picks its material from `materials` by index.
+    SphereSet {
+        particles: PathBuf,
+        materials: Vec<Material>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename = "Keyframe")]
+pub(crate) struct Keyframe {
+    pub(crate) time: Value,
+    pub(crate) offset: Option<(Value, Value, Value)>,
+    pub(crate) rotation: Option<(Value, Value, Value)>,
+    pub(crate) scale: Option<(Value, Value, Value)>,
+}
+
+/// Two opposite corners of a box
+pub(crate) type Bounds = ((Value, Value, Value), (Value, Value, Value));
+
+/// Distance function expressions, used by `Hittable::Sdf`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename = "SdfShape")]
+pub(crate) enum SdfShape {
+    Sphere {
+        radius: Value,
+    },
+    Box {
+        size: (Value, Value, Value),
+        rounding: Option<Value>,
+    },
+    Torus {
+        major_radius: Value,
+        minor_radius: Value,
+    },
+    Mandelbulb {
+        power: Option<Value>,
+        iterations: Option<u32>,
+    },
+    Union {
+        a: Box<SdfShape>,
+        b: Box<SdfShape>,
+        smoothness: Option<Value>,
+    },
+    Intersection {
+        a: Box<SdfShape>,
+        b: Box<SdfShape>,
+        smoothness: Option<Value>,
+    },
+    /// `a` with `b` cut out of it
+    Subtraction {
+        a: Box<SdfShape>,
+        b: Box<SdfShape>,
+        smoothness: Option<Value>,
+    },
+    Translate {
+        offset: (Value, Value, Value),
+        shape: Box<SdfShape>,
+    },
+    Scale {
+        factor: Value,
+        shape: Box<SdfShape>,
+    },
+    Repeat {
+        period: (Value, Value, Value),
+        shape: Box<SdfShape>,
+    },
+    /// Twists around the Y axis, by `rate` degrees per unit of height
+    Twist {
+        rate: Value,
+        shape: Box<SdfShape>,
+    },
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -111,12 +352,57 @@ pub(crate) enum Material {
         albedo: (Value, Value, Value),
         fuzziness: Value,
     },
+    /// Glass, water and so on. `roughness`, from 0 to 1, frosts the surface,
+    /// and defaults to 0. Light traveling inside is tinted to `color` every
+    /// `1 / density` units, where `density` defaults to 1, so that thicker
+    /// parts look deeper.
+    ///
+    /// Where dielectrics overlap, the one with the highest `priority`, which
+    /// defaults to 0, fills the overlap. Modelling a glass of water with the
+    /// water slightly overlapping the glass, and given a higher priority,
+    /// makes light bend by the right amount where the two meet.
     Dielectric {
         index_of_refraction: Value,
+        roughness: Option<Value>,
+        color: Option<(Value, Value, Value)>,
+        density: Option<Value>,
+        priority: Option<Value>,
+    },
+    /// A rough metal, whose color comes from `metal`'s complex index of
+    /// refraction. `roughness` defaults to 0, and `anisotropy`, from 0 to 1,
+    /// stretches highlights along the surface.
+    Conductor {
+        metal: ComplexIor,
+        roughness: Option<Value>,
+        anisotropy: Option<Value>,
     },
     DiffuseLight {
         color: TextureDesc,
     },
+    /// For `Curves`. `color` tints light each time it passes through a
+    /// fiber, and `roughness` is in degrees.
+    Hair {
+        color: TextureDesc,
+        roughness: Option<Value>,
+        index_of_refraction: Option<Value>,
+    },
+    /// Disney's principled BSDF, which can stand in for most of the other
+    /// materials. Every parameter but `base_color` is from 0 to 1, and is a
+    /// texture, usually `Gray`. `roughness` and `specular` default to 0.5,
+    /// `clearcoat_gloss` to 1, and the rest to 0. `index_of_refraction`,
+    /// which defaults to 1.5, is for transmission.
+    Principled {
+        base_color: TextureDesc,
+        metallic: Option<Box<TextureDesc>>,
+        roughness: Option<Box<TextureDesc>>,
+        specular: Option<Box<TextureDesc>>,
+        transmission: Option<Box<TextureDesc>>,
+        clearcoat: Option<Box<TextureDesc>>,
+        clearcoat_gloss: Option<Box<TextureDesc>>,
+        sheen: Option<Box<TextureDesc>>,
+        subsurface: Option<Box<TextureDesc>>,
+        index_of_refraction: Option<Value>,
+    },
     RandomChoice(Vec<Material>),
     RandomChoiceWeighted(Vec<(f64, Box<Material>)>),
 }
@@ -124,17 +410,21 @@ pub(crate) enum Material {
 impl Value {
     pub(crate) fn eval(&self, loader: &SceneLoader) -> Result<f64> {
         match self {
-            Value::Var(var) => loader
-                .pattern_vars
-                .get(var)
-                .ok_or_else(|| anyhow!("Variable {} not found", var))
-                .map(|n| *n as f64),
+            Value::Var(var) => {
+                loader.reads.borrow_mut().vars.insert(var.clone());
+                loader
+                    .pattern_vars
+                    .get(var)
+                    .ok_or_else(|| anyhow!("Variable {} not found", var))
+                    .map(|n| *n as f64)
+            }
             Value::Number(n) => Ok(*n),
             Value::BinOp(op, a, b) => {
                 let a = a.eval(loader)?;
                 let b = b.eval(loader)?;
                 match op {
                     BinOp::Rand => {
+                        loader.reads.borrow_mut().random = true;
                         let mut rng = thread_rng();
                         Ok(rng.gen_range(a..b))
                     }
@@ -175,3 +465,37 @@ impl From<Axis> for crate::geom::Axis {
         }
     }
 }
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone)]
+#[serde(rename = "CsgOp")]
+pub(crate) enum CsgOp {
+    Union,
+    Intersection,
+    Difference,
+}
+
+impl From<CsgOp> for crate::hittable::CsgOp {
+    fn from(value: CsgOp) -> Self {
+        match value {
+            CsgOp::Union => crate::hittable::CsgOp::Union,
+            CsgOp::Intersection => crate::hittable::CsgOp::Intersection,
+            CsgOp::Difference => crate::hittable::CsgOp::Difference,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone)]
+#[serde(rename = "CurveShape")]
+pub(crate) enum CurveShape {
+    Ribbon,
+    Tube,
+}
+
+impl From<CurveShape> for crate::hittable::CurveShape {
+    fn from(value: CurveShape) -> Self {
+        match value {
+            CurveShape::Ribbon => crate::hittable::CurveShape::Ribbon,
+            CurveShape::Tube => crate::hittable::CurveShape::Tube,
+        }
+    }
+}
@@ -1,19 +1,27 @@
 use crate::{
+    atmosphere::{Atmosphere, Falloff},
     camera::Camera,
     config::Scene,
-    geom::{Color, Vec3},
-    hittable::{self, AxisAlignedRect, BvhNode, Cuboid, Hittable, RotateY, Translate, ConstantMedium},
+    geom::{Aabb, Axis, Color, Pose, Transform, Vec3},
+    hittable::{
+        self, Animated, AxisAlignedRect, BvhNode, BvhOptions, Capsule, Cone, ConstantMedium, Csg,
+        Cuboid, Curves, Cylinder, Emission, Heightfield, HeterogeneousMedium, Hittable,
+        HittableList, Instance, Sdf, SdfNode, SphereSet, Torus,
+    },
     material,
     scene::desc,
-    texture::{self, Texture},
+    texture::{self, Texture, Volume},
 };
 use anyhow::{anyhow, Context, Result};
 use rand::{prelude::Distribution, thread_rng, Rng};
 use ron::extensions::Extensions;
 use std::{
-    collections::HashMap,
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    ops::Range,
     path::{Path, PathBuf},
     sync::Arc,
+    time::Instant,
 };
 
 #[derive(Default)]
@@ -27,12 +35,40 @@ impl HittableAccum {
     fn add_many<I: Iterator<Item = Box<dyn Hittable>>>(&mut self, iter: I) {
         self.0.extend(iter);
     }
+
+    /// Collapses everything that has been added into a single hittable.
+    /// Groups too big for one BVH leaf get a BVH of their own, which the
+    /// world's BVH treats as one object.
+    fn into_single(mut self, time_range: Range<f64>, options: &BvhOptions) -> Box<dyn Hittable> {
+        match self.0.len() {
+            1 => self.0.remove(0),
+            n if n <= options.max_leaf_size => Box::new(HittableList::new(self.0)),
+            _ => Box::new(BvhNode::with_options(time_range, self.0, options)),
+        }
+    }
+}
+
+/// What realizing part of a scene has read that could come out differently
+/// each time it is realized.
+#[derive(Default)]
+pub(crate) struct Reads {
+    pub(crate) vars: HashSet<String>,
+    pub(crate) random: bool,
 }
 
 pub struct SceneLoader {
     pub(crate) scene_path: PathBuf,
     pub(crate) pattern_vars: HashMap<String, i32>,
     pub(crate) materials: HashMap<String, Arc<dyn material::Material>>,
+    pub(crate) reads: RefCell<Reads>,
+    definition_descs: HashMap<String, Vec<desc::Hittable>>,
+    definitions: HashMap<String, Arc<dyn Hittable>>,
+    /// Transformed objects that come out the same every time, by the address
+    /// of their description, so that copies of them share their geometry.
+    /// The description is kept alive so that its address isn't reused.
+    shared: HashMap<usize, (Arc<desc::Hittable>, Arc<dyn Hittable>)>,
+    time_range: Range<f64>,
+    bvh_options: BvhOptions,
 }
 
 impl SceneLoader {
@@ -41,6 +77,21 @@ impl SceneLoader {
             scene_path: path.into(),
             pattern_vars: HashMap::default(),
             materials: HashMap::default(),
+            reads: RefCell::default(),
+            definition_descs: HashMap::default(),
+            definitions: HashMap::default(),
+            shared: HashMap::default(),
+            time_range: 0.0..0.0,
+            bvh_options: BvhOptions::default(),
+        }
+    }
+
+    /// Sets how the BVHs for the world and shared definitions are built.
+    #[must_use]
+    pub fn bvh_options(self, bvh_options: BvhOptions) -> Self {
+        Self {
+            bvh_options,
+            ..self
         }
     }
 
@@ -56,11 +107,6 @@ impl SceneLoader {
             self.materials.insert(key, material);
         }
 
-        let mut hittables = HittableAccum::default();
-        for desc in scene_desc.objects {
-            self.realize_hittable(desc, &mut hittables)?;
-        }
-
         let mut camera_builder = Camera::build()
             .look_from(scene_desc.camera.look_from)
             .aspect_ratio(scene_desc.image.width as f64 / scene_desc.image.height as f64)
@@ -82,16 +128,57 @@ impl SceneLoader {
 
         let camera = camera_builder.done()?;
 
+        self.time_range = camera.shutter_time.clone();
+        self.definition_descs = scene_desc.definitions;
+
+        let mut hittables = HittableAccum::default();
+        for desc in scene_desc.objects {
+            self.realize_hittable(desc, &mut hittables)?;
+        }
+
+        let build_start = Instant::now();
+        let world = BvhNode::with_options(self.time_range.clone(), hittables.0, &self.bvh_options);
+        let build_time = build_start.elapsed();
+
         Ok(Scene {
-            world: BvhNode::new(camera.shutter_time.clone(), hittables.0),
+            world,
             camera,
             image: scene_desc.image,
             background: scene_desc
                 .background
                 .map_or_else(|| Ok(Color::black()), |v| self.eval_vec3(v))?,
+            atmosphere: scene_desc
+                .atmosphere
+                .map(|a| self.realize_atmosphere(a))
+                .transpose()
+                .context("realizing atmosphere")?,
+            build_time,
         })
     }
 
+    fn realize_atmosphere(&self, desc: desc::Atmosphere) -> Result<Atmosphere> {
+        let coefficient = |c: Option<(desc::Value, desc::Value, desc::Value)>| {
+            c.map_or_else(|| Ok(Color::black()), |c| self.eval_vec3(c))
+        };
+        let falloff = match (desc.height, desc.scale_height) {
+            (None, None) => None,
+            (height, Some(scale_height)) => Some(Falloff {
+                height: height.map_or(Ok(0.0), |h| h.eval(self))?,
+                scale_height: scale_height.eval(self)?,
+            }),
+            (Some(_), None) => return Err(anyhow!("height falloff needs a scale_height")),
+        };
+        Ok(Atmosphere {
+            absorption: coefficient(desc.absorption).context("evaluating absorption")?,
+            scattering: coefficient(desc.scattering).context("evaluating scattering")?,
+            falloff,
+            // the atmosphere weights scattering by its own color
+            phase_function: self
+                .realize_phase_function(desc.phase, desc::TextureDesc::Solid(1.0, 1.0, 1.0))?,
+        })
+    }
+
+    #[allow(clippy::too_many_lines)]
     fn realize_hittable(
         &mut self,
         hittable: desc::Hittable,
@@ -148,47 +235,364 @@ impl SceneLoader {
                 self.realize_pattern(&var, &range[..], &object, hittables)?;
             }
 
-            desc::Hittable::Translate { offset, hittable } => {
-                let offset = self.eval_vec3(offset)?;
+            desc::Hittable::Translate { offset, hittable } => hittables.add(Instance {
+                transform: Transform::translate(self.eval_vec3(offset)?),
+                object: self.realize_shared(hittable)?,
+            }),
+
+            desc::Hittable::RotateY { angle, hittable } => hittables.add(Instance {
+                transform: Transform::rotate(Axis::Y, angle.eval(self)?.to_radians()),
+                object: self.realize_shared(hittable)?,
+            }),
+
+            desc::Hittable::ConstantMedium {
+                boundary,
+                density,
+                texture: color,
+                phase,
+            } => {
                 let mut inner = HittableAccum::default();
-                self.realize_hittable(*hittable, &mut inner)?;
+                let phase_function = self.realize_phase_function(phase, color)?;
+                let density = density.eval(self)?;
+                self.realize_hittable(*boundary, &mut inner)?;
                 hittables.add_many(inner.0.into_iter().map(|h| {
-                    Box::new(Translate {
-                        offset,
-                        hittable: h,
-                    }) as Box<dyn Hittable>
+                    Box::new(ConstantMedium::new(h, density, phase_function.clone()))
+                        as Box<dyn Hittable>
                 }));
             }
 
-            desc::Hittable::RotateY { angle, hittable } => {
-                let theta = angle.eval(self)?.to_radians();
+            desc::Hittable::HeterogeneousMedium {
+                boundary,
+                volume,
+                texture,
+                phase,
+                emission,
+            } => {
                 let mut inner = HittableAccum::default();
-                self.realize_hittable(*hittable, &mut inner)?;
-                hittables.add_many(
-                    inner
-                        .0
-                        .into_iter()
-                        .map(|h| Box::new(RotateY::new(h, theta)) as Box<dyn Hittable>),
+                let phase_function = self.realize_phase_function(phase, texture.clone())?;
+                self.realize_hittable(*boundary, &mut inner)?;
+                for boundary in inner.0 {
+                    let bounding_box = boundary.bounding_box(self.time_range.clone());
+                    let volume = self
+                        .realize_volume(volume.clone(), bounding_box.clone())
+                        .context("realizing volume")?;
+                    let mut medium =
+                        HeterogeneousMedium::new(boundary, volume, phase_function.clone());
+                    if let Some(emission) = &emission {
+                        medium = medium.with_emission(
+                            Emission {
+                                volume: self
+                                    .realize_volume(emission.volume.clone(), bounding_box)
+                                    .context("realizing emission")?,
+                                color: self.eval_vec3(emission.color.clone())?,
+                            },
+                            self.realize_texture(texture.clone())?,
+                        );
+                    }
+                    hittables.add(medium);
+                }
+            }
+
+            desc::Hittable::Cylinder {
+                base,
+                top,
+                radius,
+                capped,
+                material,
+            } => hittables.add(Cylinder::new(
+                self.eval_vec3(base)?,
+                self.eval_vec3(top)?,
+                radius.eval(self)?,
+                capped.unwrap_or(true),
+                self.realize_material(material)?,
+            )),
+
+            desc::Hittable::Cone {
+                base,
+                top,
+                base_radius,
+                top_radius,
+                capped,
+                material,
+            } => hittables.add(Cone::new(
+                self.eval_vec3(base)?,
+                self.eval_vec3(top)?,
+                base_radius.eval(self)?,
+                top_radius.map_or(Ok(0.0), |r| r.eval(self))?,
+                capped.unwrap_or(true),
+                self.realize_material(material)?,
+            )),
+
+            desc::Hittable::Torus {
+                center,
+                axis,
+                major_radius,
+                minor_radius,
+                material,
+            } => hittables.add(Torus::new(
+                self.eval_vec3(center)?,
+                axis.map_or_else(|| Ok(Vec3::new(0.0, 1.0, 0.0)), |a| self.eval_vec3(a))?,
+                major_radius.eval(self)?,
+                minor_radius.eval(self)?,
+                self.realize_material(material)?,
+            )),
+
+            desc::Hittable::Capsule {
+                base,
+                top,
+                radius,
+                material,
+            } => hittables.add(Capsule::new(
+                self.eval_vec3(base)?,
+                self.eval_vec3(top)?,
+                radius.eval(self)?,
+                self.realize_material(material)?,
+            )),
+
+            desc::Hittable::Csg { op, left, right } => hittables.add(Csg {
+                op: op.into(),
+                left: self.realize_single(*left)?,
+                right: self.realize_single(*right)?,
+            }),
+
+            desc::Hittable::Sdf {
+                shape,
+                bounds,
+                material,
+            } => {
+                let bounds = match bounds {
+                    Some((a, b)) => Some(Aabb::new(self.eval_vec3(a)?, self.eval_vec3(b)?)),
+                    None => None,
+                };
+                hittables.add(
+                    Sdf::new(
+                        self.realize_sdf(shape)?,
+                        bounds,
+                        self.realize_material(material)?,
+                    )
+                    .ok_or_else(|| {
+                        anyhow!("Sdf shape is unbounded, so it needs explicit bounds")
+                    })?,
                 );
             }
 
-            desc::Hittable::ConstantMedium { boundary, density, texture: color } => {
-                let mut inner = HittableAccum::default();
-                let texture = self.realize_texture(color)?;
-                let density = density.eval(self)?;
-                self.realize_hittable(*boundary, &mut inner)?;
-                hittables.add_many(
-                    inner
-                        .0
-                        .into_iter()
-                        .map(|h| Box::new(ConstantMedium::new(h, density, texture.clone())) as Box<dyn Hittable>),
+            desc::Hittable::Instance {
+                object,
+                offset,
+                rotation,
+                scale,
+            } => {
+                hittables.add(Instance {
+                    object: self.realize_definition(&object)?,
+                    transform: self.eval_pose(offset, rotation, scale)?.transform(),
+                });
+            }
+
+            desc::Hittable::Animated {
+                keyframes,
+                hittable,
+            } => {
+                let keyframes = keyframes
+                    .into_iter()
+                    .map(|keyframe| {
+                        Ok((
+                            keyframe.time.eval(self)?,
+                            self.eval_pose(keyframe.offset, keyframe.rotation, keyframe.scale)?,
+                        ))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                hittables.add(Animated::new(self.realize_shared(hittable)?, keyframes));
+            }
+
+            desc::Hittable::Heightfield {
+                image,
+                center,
+                size,
+                height,
+                material,
+            } => {
+                let path = self.resolve_path(&image);
+                hittables.add(
+                    Heightfield::new(
+                        &path,
+                        center.map_or_else(|| Ok(Vec3::default()), |c| self.eval_vec3(c))?,
+                        (size.0.eval(self)?, size.1.eval(self)?),
+                        height.eval(self)?,
+                        self.realize_material(material)?,
+                    )
+                    .context(format!(
+                        "Loading heightfield from {}",
+                        path.to_string_lossy()
+                    ))?,
                 );
             }
-        };
+
+            desc::Hittable::Curves {
+                strands,
+                shape,
+                material,
+            } => {
+                let path = self.resolve_path(&strands);
+                hittables.add(
+                    Curves::load(
+                        &path,
+                        shape.map(Into::into).unwrap_or_default(),
+                        &self.realize_material(material)?,
+                    )
+                    .context(format!("Loading strands from {}", path.to_string_lossy()))?,
+                );
+            }
+
+            desc::Hittable::SphereSet {
+                particles,
+                materials,
+            } => {
+                let path = self.resolve_path(&particles);
+                let materials = materials
+                    .into_iter()
+                    .map(|m| self.realize_material(m))
+                    .collect::<Result<Vec<_>>>()?;
+                hittables.add(
+                    SphereSet::load(&path, materials)
+                        .context(format!("Loading particles from {}", path.to_string_lossy()))?,
+                );
+            }
+        }
         Ok(())
     }
 
-    #[allow(clippy::too_many_lines)]
+    fn realize_sdf(&self, shape: desc::SdfShape) -> Result<SdfNode> {
+        let smoothness = |s: Option<desc::Value>| s.map_or(Ok(0.0), |s| s.eval(self));
+        Ok(match shape {
+            desc::SdfShape::Sphere { radius } => SdfNode::Sphere {
+                radius: radius.eval(self)?,
+            },
+            desc::SdfShape::Box { size, rounding } => SdfNode::Box {
+                half_size: self.eval_vec3(size)? / 2.0,
+                rounding: rounding.map_or(Ok(0.0), |r| r.eval(self))?,
+            },
+            desc::SdfShape::Torus {
+                major_radius,
+                minor_radius,
+            } => SdfNode::Torus {
+                major_radius: major_radius.eval(self)?,
+                minor_radius: minor_radius.eval(self)?,
+            },
+            desc::SdfShape::Mandelbulb { power, iterations } => SdfNode::Mandelbulb {
+                power: power.map_or(Ok(8.0), |p| p.eval(self))?,
+                iterations: iterations.unwrap_or(12),
+            },
+            desc::SdfShape::Union {
+                a,
+                b,
+                smoothness: k,
+            } => SdfNode::Union(
+                Box::new(self.realize_sdf(*a)?),
+                Box::new(self.realize_sdf(*b)?),
+                smoothness(k)?,
+            ),
+            desc::SdfShape::Intersection {
+                a,
+                b,
+                smoothness: k,
+            } => SdfNode::Intersection(
+                Box::new(self.realize_sdf(*a)?),
+                Box::new(self.realize_sdf(*b)?),
+                smoothness(k)?,
+            ),
+            desc::SdfShape::Subtraction {
+                a,
+                b,
+                smoothness: k,
+            } => SdfNode::Subtraction(
+                Box::new(self.realize_sdf(*a)?),
+                Box::new(self.realize_sdf(*b)?),
+                smoothness(k)?,
+            ),
+            desc::SdfShape::Translate { offset, shape } => SdfNode::Translate {
+                offset: self.eval_vec3(offset)?,
+                node: Box::new(self.realize_sdf(*shape)?),
+            },
+            desc::SdfShape::Scale { factor, shape } => SdfNode::Scale {
+                factor: factor.eval(self)?,
+                node: Box::new(self.realize_sdf(*shape)?),
+            },
+            desc::SdfShape::Repeat { period, shape } => SdfNode::Repeat {
+                period: self.eval_vec3(period)?,
+                node: Box::new(self.realize_sdf(*shape)?),
+            },
+            desc::SdfShape::Twist { rate, shape } => SdfNode::Twist {
+                rate: rate.eval(self)?.to_radians(),
+                node: Box::new(self.realize_sdf(*shape)?),
+            },
+        })
+    }
+
+    /// Realizes one of the scene's definitions the first time it is used,
+    /// with its own BVH, and shares it after that.
+    fn realize_definition(&mut self, name: &str) -> Result<Arc<dyn Hittable>> {
+        if let Some(object) = self.definitions.get(name) {
+            return Ok(object.clone());
+        }
+
+        // Taking the description out while realizing it catches definitions
+        // that contain themselves. Definitions are only realized once, so
+        // they shouldn't depend on the pattern they were first used in.
+        let descs = self
+            .definition_descs
+            .remove(name)
+            .ok_or_else(|| anyhow!("Definition {} not found, or contains itself", name))?;
+        let pattern_vars = std::mem::take(&mut self.pattern_vars);
+        let mut parts = HittableAccum::default();
+        for desc in descs {
+            self.realize_hittable(desc, &mut parts)?;
+        }
+        self.pattern_vars = pattern_vars;
+
+        let object: Arc<dyn Hittable> = Arc::new(BvhNode::with_options(
+            self.time_range.clone(),
+            parts.0,
+            &self.bvh_options,
+        ));
+        self.definitions.insert(name.to_string(), object.clone());
+        Ok(object)
+    }
+
+    /// Realizes a description that may expand to several objects, such as a
+    /// `Pattern`, as a single object.
+    fn realize_single(&mut self, hittable: desc::Hittable) -> Result<Box<dyn Hittable>> {
+        let mut inner = HittableAccum::default();
+        self.realize_hittable(hittable, &mut inner)?;
+        Ok(inner.into_single(self.time_range.clone(), &self.bvh_options))
+    }
+
+    /// Realizes a description as a single object to be placed with a
+    /// transform. Descriptions that come out the same every time, since they
+    /// read no pattern variables from outside and no random numbers, are
+    /// realized once and then shared, so a pattern of moved copies of an
+    /// object, with its own BVH, stores its geometry once.
+    fn realize_shared(&mut self, hittable: Arc<desc::Hittable>) -> Result<Arc<dyn Hittable>> {
+        // copies of a description made by cloning its parents all point to
+        // the same one
+        let key = Arc::as_ptr(&hittable) as usize;
+        if let Some((_, object)) = self.shared.get(&key) {
+            return Ok(object.clone());
+        }
+
+        let outer_reads = self.reads.take();
+        let bound: HashSet<String> = self.pattern_vars.keys().cloned().collect();
+        let object: Arc<dyn Hittable> = self.realize_single((*hittable).clone())?.into();
+        let reads = self.reads.replace(outer_reads);
+        if !reads.random && reads.vars.is_disjoint(&bound) {
+            self.shared.insert(key, (hittable, object.clone()));
+        }
+
+        let mut all_reads = self.reads.borrow_mut();
+        all_reads.vars.extend(reads.vars);
+        all_reads.random |= reads.random;
+        Ok(object)
+    }
+
     fn realize_pattern(
         &mut self,
         var: &str,
@@ -205,111 +609,20 @@ impl SceneLoader {
             }
         };
 
+        let outer = self.pattern_vars.get(var).copied();
         for val in range {
             self.pattern_vars.insert(var.to_string(), val);
-            match object {
-                desc::Hittable::Sphere {
-                    ref center,
-                    ref radius,
-                    ref material,
-                } => hittables.add(hittable::Sphere {
-                    center: self.eval_vec3(center.clone())?,
-                    radius: radius.eval(self)?,
-                    material: self.realize_material((*material).clone())?,
-                }),
-
-                desc::Hittable::MovingSphere {
-                    ref center,
-                    ref time,
-                    ref radius,
-                    ref material,
-                } => {
-                    let c1 = self.eval_vec3(center.0.clone())?;
-                    let c2 = self.eval_vec3(center.1.clone())?;
-                    hittables.add(hittable::MovingSphere {
-                        center: c1..c2,
-                        time: (time.0.eval(self)?)..(time.1.eval(self)?),
-                        radius: radius.eval(self)?,
-                        material: self.realize_material((*material).clone())?,
-                    });
-                }
-
-                desc::Hittable::AARect {
-                    ref center,
-                    ref width,
-                    ref height,
-                    ref axis,
-                    ref material,
-                } => hittables.add(AxisAlignedRect {
-                    center: self.eval_vec3(center.clone())?,
-                    width: width.eval(self)?,
-                    height: height.eval(self)?,
-                    axis: (*axis).into(),
-                    material: self.realize_material((*material).clone())?,
-                }),
-
-                desc::Hittable::Cuboid {
-                    ref center,
-                    ref size,
-                    ref material,
-                } => hittables.add(Cuboid::new(
-                    center
-                        .clone()
-                        .map_or_else(|| Ok(Vec3::default()), |c| self.eval_vec3(c))?,
-                    self.eval_vec3(size.clone())?,
-                    &self.realize_material((*material).clone())?,
-                )),
-
-                desc::Hittable::Pattern {
-                    ref var,
-                    ref range,
-                    ref object,
-                } => {
-                    self.realize_pattern(var, range, object, hittables)?;
-                }
-
-                desc::Hittable::Translate { offset, hittable } => {
-                    let offset = self.eval_vec3(offset.clone())?;
-                    let mut inner = HittableAccum::default();
-                    self.realize_hittable((**hittable).clone(), &mut inner)?;
-                    hittables.add_many(inner.0.into_iter().map(|h| {
-                        Box::new(Translate {
-                            offset,
-                            hittable: h,
-                        }) as Box<dyn Hittable>
-                    }));
-                }
-
-                desc::Hittable::RotateY { angle, hittable } => {
-                    let theta = angle.eval(self)?.to_radians();
-                    let mut inner = HittableAccum::default();
-                    self.realize_hittable((**hittable).clone(), &mut inner)?;
-                    hittables.add_many(
-                        inner
-                            .0
-                            .into_iter()
-                            .map(|h| Box::new(RotateY::new(h, theta)) as Box<dyn Hittable>),
-                    );
-                }
-
-                desc::Hittable::ConstantMedium { boundary, density, texture: color } => {
-                    let mut inner = HittableAccum::default();
-                    let texture = self.realize_texture((*color).clone())?;
-                    let density = density.eval(self)?;
-                    self.realize_hittable((**boundary).clone(), &mut inner)?;
-                    hittables.add_many(
-                        inner
-                            .0
-                            .into_iter()
-                            .map(|h| Box::new(ConstantMedium::new(h, density, texture.clone())) as Box<dyn Hittable>),
-                    );
-                }
-            }
+            self.realize_hittable(object.clone(), hittables)?;
         }
+        match outer {
+            Some(val) => self.pattern_vars.insert(var.to_string(), val),
+            None => self.pattern_vars.remove(var),
+        };
 
         Ok(())
     }
 
+    #[allow(clippy::too_many_lines)]
     pub(crate) fn realize_material<D: Into<desc::Material>>(
         &self,
         desc: D,
@@ -326,19 +639,95 @@ impl SceneLoader {
             }),
             desc::Material::Dielectric {
                 index_of_refraction,
+                roughness,
+                color,
+                density,
+                priority,
             } => Arc::new(material::Dielectric {
                 index_of_refraction: index_of_refraction
                     .eval(self)
                     .context("evaluating index_of_refraction")?,
+                roughness: roughness
+                    .map_or(Ok(0.0), |r| r.eval(self))
+                    .context("evaluating roughness")?,
+                absorption: material::Dielectric::absorption(
+                    color.map_or(Ok(Color::white()), |c| self.eval_vec3(c))?,
+                    density
+                        .map_or(Ok(1.0), |d| d.eval(self))
+                        .context("evaluating density")?,
+                ),
+                priority: priority
+                    .map_or(Ok(0.0), |p| p.eval(self))
+                    .context("evaluating priority")?,
             }),
             desc::Material::Metal { albedo, fuzziness } => Arc::new(material::Metal {
                 albedo: self.eval_vec3(albedo)?,
                 fuzziness: fuzziness.eval(self).context("evaluating fuzziness")?,
             }),
+            desc::Material::Conductor {
+                metal,
+                roughness,
+                anisotropy,
+            } => Arc::new(material::Conductor::new(
+                self.realize_complex_ior(metal)?,
+                roughness
+                    .map_or(Ok(0.0), |r| r.eval(self))
+                    .context("evaluating roughness")?,
+                anisotropy
+                    .map_or(Ok(0.0), |a| a.eval(self))
+                    .context("evaluating anisotropy")?,
+            )),
             desc::Material::DiffuseLight { color } => Arc::new(material::DiffuseLight {
                 texture: self.realize_texture(color)?,
             }),
+            desc::Material::Hair {
+                color,
+                roughness,
+                index_of_refraction,
+            } => Arc::new(material::Hair {
+                color: self.realize_texture(color)?,
+                roughness: roughness
+                    .map_or(Ok(5_f64.to_radians()), |r| {
+                        r.eval(self).map(f64::to_radians)
+                    })
+                    .context("evaluating roughness")?,
+                index_of_refraction: index_of_refraction
+                    .map_or(Ok(1.55), |i| i.eval(self))
+                    .context("evaluating index_of_refraction")?,
+            }),
+            desc::Material::Principled {
+                base_color,
+                metallic,
+                roughness,
+                specular,
+                transmission,
+                clearcoat,
+                clearcoat_gloss,
+                sheen,
+                subsurface,
+                index_of_refraction,
+            } => Arc::new(material::Principled {
+                base_color: self.realize_texture(base_color)?,
+                metallic: self.realize_param(metallic, 0.0).context("realizing metallic")?,
+                roughness: self.realize_param(roughness, 0.5).context("realizing roughness")?,
+                specular: self.realize_param(specular, 0.5).context("realizing specular")?,
+                transmission: self
+                    .realize_param(transmission, 0.0)
+                    .context("realizing transmission")?,
+                clearcoat: self.realize_param(clearcoat, 0.0).context("realizing clearcoat")?,
+                clearcoat_gloss: self
+                    .realize_param(clearcoat_gloss, 1.0)
+                    .context("realizing clearcoat_gloss")?,
+                sheen: self.realize_param(sheen, 0.0).context("realizing sheen")?,
+                subsurface: self
+                    .realize_param(subsurface, 0.0)
+                    .context("realizing subsurface")?,
+                index_of_refraction: index_of_refraction
+                    .map_or(Ok(1.5), |i| i.eval(self))
+                    .context("evaluating index_of_refraction")?,
+            }),
             desc::Material::RandomChoice(options) => {
+                self.reads.borrow_mut().random = true;
                 let mut rng = thread_rng();
                 let idx = rng.gen_range(0..options.len());
                 self.realize_material((options[idx]).clone())?
@@ -346,6 +735,7 @@ impl SceneLoader {
             desc::Material::RandomChoiceWeighted(options) => {
                 let dist = rand::distributions::WeightedIndex::new(options.iter().map(|c| c.0))
                     .context("generating weighted distribution")?;
+                self.reads.borrow_mut().random = true;
                 let mut rng = thread_rng();
                 let idx = dist.sample(&mut rng);
                 self.realize_material((*options[idx].1).clone())?
@@ -353,9 +743,106 @@ impl SceneLoader {
         })
     }
 
+    fn realize_complex_ior(&self, desc: desc::ComplexIor) -> Result<material::ComplexIor> {
+        Ok(match desc {
+            desc::ComplexIor::Gold => material::ComplexIor::gold(),
+            desc::ComplexIor::Copper => material::ComplexIor::copper(),
+            desc::ComplexIor::Aluminium => material::ComplexIor::aluminium(),
+            desc::ComplexIor::Silver => material::ComplexIor::silver(),
+            desc::ComplexIor::Custom { eta, k } => material::ComplexIor {
+                eta: self.eval_vec3(eta).context("evaluating eta")?,
+                k: self.eval_vec3(k).context("evaluating k")?,
+            },
+        })
+    }
+
+    fn realize_phase_function(
+        &self,
+        desc: Option<desc::PhaseFunction>,
+        albedo: desc::TextureDesc,
+    ) -> Result<Arc<dyn material::Material>> {
+        let albedo = self.realize_texture(albedo)?;
+        Ok(match desc {
+            None | Some(desc::PhaseFunction::Isotropic) => Arc::new(texture::Isotropic { albedo }),
+            Some(desc::PhaseFunction::HenyeyGreenstein(g)) => Arc::new(texture::HenyeyGreenstein {
+                albedo,
+                g: g.eval(self).context("evaluating g")?,
+                second_lobe: None,
+            }),
+            Some(desc::PhaseFunction::TwoLobe {
+                first,
+                second,
+                weight,
+            }) => Arc::new(texture::HenyeyGreenstein {
+                albedo,
+                g: first.eval(self).context("evaluating first")?,
+                second_lobe: Some(texture::Lobe {
+                    g: second.eval(self).context("evaluating second")?,
+                    weight: weight.eval(self).context("evaluating weight")?,
+                }),
+            }),
+        })
+    }
+
+    fn realize_volume(
+        &self,
+        desc: desc::VolumeDesc,
+        boundary_box: Option<Aabb>,
+    ) -> Result<Box<dyn Volume>> {
+        Ok(match desc {
+            desc::VolumeDesc::Noise {
+                density,
+                frequency,
+                octaves,
+                threshold,
+            } => Box::new(texture::Noise::new(
+                density.eval(self).context("evaluating density")?,
+                frequency.map_or(Ok(1.0), |f| f.eval(self))?,
+                octaves.unwrap_or(5),
+                threshold.map_or(Ok(0.0), |t| t.eval(self))?,
+            )),
+            desc::VolumeDesc::Turbulence {
+                density,
+                frequency,
+                octaves,
+            } => Box::new(texture::Turbulence::new(
+                density.eval(self).context("evaluating density")?,
+                frequency.map_or(Ok(1.0), |f| f.eval(self))?,
+                octaves.unwrap_or(7),
+            )),
+            desc::VolumeDesc::Grid {
+                path,
+                scale,
+                bounds,
+            } => {
+                let bounds = match bounds {
+                    Some((a, b)) => Aabb::new(self.eval_vec3(a)?, self.eval_vec3(b)?),
+                    None => boundary_box
+                        .ok_or_else(|| anyhow!("voxel grids need bounds for unbounded objects"))?,
+                };
+                let path = self.resolve_path(&path);
+                Box::new(
+                    texture::VoxelGrid::load(
+                        &path,
+                        bounds,
+                        scale.map_or(Ok(1.0), |s| s.eval(self))?,
+                    )
+                    .context(format!(
+                        "Loading voxel grid from {}",
+                        path.to_string_lossy()
+                    ))?,
+                )
+            }
+        })
+    }
+
     pub(crate) fn realize_texture(&self, desc: desc::TextureDesc) -> Result<Box<dyn Texture>> {
         Ok(match desc {
             desc::TextureDesc::Solid(r, g, b) => Box::new(texture::SolidColor(Color::new(r, g, b))),
+            desc::TextureDesc::Gray(value) => {
+                let value = value.eval(self).context("evaluating gray")?;
+                Box::new(texture::SolidColor(Color::new(value, value, value)))
+            }
             desc::TextureDesc::Checkerboard(even, odd) => Box::new(texture::Checkerboard::new(
                 self.realize_texture(*even)?,
                 self.realize_texture(*odd)?,
@@ -363,9 +850,7 @@ impl SceneLoader {
             desc::TextureDesc::Perlin => Box::<texture::Perlin>::default(),
             desc::TextureDesc::Image(path) => {
                 let original = path.to_string_lossy().to_string();
-                let mut dir = self.scene_path.clone();
-                dir.pop();
-                let adjusted_path = dir.join(path);
+                let adjusted_path = self.resolve_path(&path);
                 let img = texture::Image::new(&adjusted_path).context(format!(
                     "Adjusted original path {} to {}",
                     original,
@@ -376,6 +861,53 @@ impl SceneLoader {
         })
     }
 
+    /// Realizes a material parameter, using a solid `default` when it isn't
+    /// given.
+    fn realize_param(
+        &self,
+        param: Option<Box<desc::TextureDesc>>,
+        default: f64,
+    ) -> Result<Box<dyn Texture>> {
+        match param {
+            Some(texture) => self.realize_texture(*texture),
+            None => Ok(Box::new(texture::SolidColor(Color::new(
+                default, default, default,
+            )))),
+        }
+    }
+
+    /// Builds a pose from optional parts, with rotation given in degrees.
+    fn eval_pose(
+        &self,
+        offset: Option<(desc::Value, desc::Value, desc::Value)>,
+        rotation: Option<(desc::Value, desc::Value, desc::Value)>,
+        scale: Option<(desc::Value, desc::Value, desc::Value)>,
+    ) -> Result<Pose> {
+        let mut pose = Pose::default();
+        if let Some(offset) = offset {
+            pose.offset = self.eval_vec3(offset)?;
+        }
+        if let Some(rotation) = rotation {
+            let degrees = self.eval_vec3(rotation)?;
+            pose.rotation = Vec3::new(
+                degrees.x().to_radians(),
+                degrees.y().to_radians(),
+                degrees.z().to_radians(),
+            );
+        }
+        if let Some(scale) = scale {
+            pose.scale = self.eval_vec3(scale)?;
+        }
+        Ok(pose)
+    }
+
+    /// Paths in scene files are relative to the scene file.
+    fn resolve_path(&self, path: &Path) -> PathBuf {
+        let mut dir = self.scene_path.clone();
+        dir.pop();
+        dir.join(path)
+    }
+
     pub(crate) fn eval_vec3(
         &self,
         (e1, e2, e3): (desc::Value, desc::Value, desc::Value),
@@ -383,3 +915,75 @@ impl SceneLoader {
         Ok(Vec3::new(e1.eval(self)?, e2.eval(self)?, e3.eval(self)?))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{HittableAccum, SceneLoader};
+    use crate::{
+        geom::{Point3, Ray, Vec3},
+        hittable::{BvhNode, Hittable},
+        scene::desc,
+    };
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+    use ron::extensions::Extensions;
+    use std::path::Path;
+
+    fn realize(loader: &mut SceneLoader, text: &str) -> Vec<Box<dyn Hittable>> {
+        let desc: desc::Hittable = ron::Options::default()
+            .with_default_extension(Extensions::IMPLICIT_SOME)
+            .from_str(text)
+            .unwrap();
+        let mut hittables = HittableAccum::default();
+        loader.realize_hittable(desc, &mut hittables).unwrap();
+        hittables.0
+    }
+
+    #[test]
+    fn test_nested_bvh() {
+        let mut loader = SceneLoader::new(Path::new("test.ron"));
+        // ten moved copies of a row of ten spheres, and the same spheres
+        // placed one by one
+        let nested = realize(
+            &mut loader,
+            r#"Pattern(var: "i", range: [10], object: Translate(
+                offset: (0, 0, ("Mult", "i", -3)),
+                hittable: Pattern(var: "j", range: [10], object: Sphere(
+                    center: ("j", 0, 0),
+                    radius: 0.4,
+                    material: Lambertian(albedo: Solid(1, 1, 1)),
+                )),
+            ))"#,
+        );
+        let flat = realize(
+            &mut loader,
+            r#"Pattern(var: "i", range: [10], object: Pattern(var: "j", range: [10], object: Sphere(
+                center: ("j", 0, ("Mult", "i", -3)),
+                radius: 0.4,
+                material: Lambertian(albedo: Solid(1, 1, 1)),
+            )))"#,
+        );
+
+        // the row is realized once, as a BVH of its own, and each copy is a
+        // single object in the world's BVH
+        assert_eq!(loader.shared.len(), 1);
+        let nested = BvhNode::new(0.0..0.0, nested);
+        let flat = BvhNode::new(0.0..0.0, flat);
+        assert_eq!(nested.stats().objects, 10);
+        assert_eq!(flat.stats().objects, 100);
+
+        let mut rng = StdRng::seed_from_u64(13);
+        for _ in 0..1000 {
+            let ray = Ray::new(
+                Point3::new(4.5, 10.0, -13.5),
+                Vec3::new(rng.gen_range(-0.5..0.5), -1.0, rng.gen_range(-1.5..1.5)),
+                0.0,
+            );
+            let expected = flat.hit(ray, 0.0..f64::INFINITY).map(|h| h.t);
+            let t = nested.hit(ray, 0.0..f64::INFINITY).map(|h| h.t);
+            assert_eq!(t.is_some(), expected.is_some());
+            if let (Some(t), Some(expected)) = (t, expected) {
+                assert!((t - expected).abs() < 1e-9);
+            }
+        }
+    }
+}
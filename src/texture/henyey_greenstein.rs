@@ -0,0 +1,85 @@
+use rand::Rng;
+use std::f64::consts::PI;
+
+use super::Texture;
+use crate::{
+    geom::{Frame, Ray, Vec3},
+    hittable::HitRecord,
+    material::{Material, ScatterResult},
+};
+
+/// A phase function that prefers to scatter light forwards (for positive
+/// `g`) or backwards (for negative `g`). This gives fog and clouds the bright
+/// halo they have when looking towards a light.
+///
+/// A second lobe can be mixed in with `weight` between 0 and 1, typically a
+/// weaker backwards one, to better match real droplets.
+#[derive(Clone)]
+pub struct HenyeyGreenstein {
+    pub albedo: Box<dyn Texture>,
+    pub g: f64,
+    pub second_lobe: Option<Lobe>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Lobe {
+    pub g: f64,
+    pub weight: f64,
+}
+
+impl HenyeyGreenstein {
+    /// Picks the cosine of the angle between the incoming and scattered
+    /// directions, by inverting the Henyey-Greenstein distribution.
+    fn sample_cos_theta(g: f64, xi: f64) -> f64 {
+        if g.abs() < 1e-3 {
+            return 1.0 - 2.0 * xi;
+        }
+        let s = (1.0 - g * g) / (1.0 - g + 2.0 * g * xi);
+        ((1.0 + g * g - s * s) / (2.0 * g)).clamp(-1.0, 1.0)
+    }
+}
+
+impl Material for HenyeyGreenstein {
+    fn scatter(&self, ray_in: &Ray, hit_record: &HitRecord) -> ScatterResult {
+        let mut rng = rand::thread_rng();
+        let g = match self.second_lobe {
+            Some(lobe) if rng.gen::<f64>() < lobe.weight => lobe.g,
+            _ => self.g,
+        };
+
+        let cos_theta = Self::sample_cos_theta(g, rng.gen());
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let (sin_phi, cos_phi) = (2.0 * PI * rng.gen::<f64>()).sin_cos();
+        let frame = Frame::new(hit_record.p, ray_in.direction);
+        let direction = frame.vector_to_world(Vec3::new(
+            sin_theta * cos_phi,
+            sin_theta * sin_phi,
+            cos_theta,
+        ));
+
+        ScatterResult {
+            attenuation: self.albedo.value(hit_record.u, hit_record.v, hit_record.p),
+            scattered_ray: Some(hit_record.spawn_ray(direction, ray_in.time)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    #[test]
+    fn test_mean_cosine() {
+        // the mean cosine of the scattering angle is g
+        let mut rng = StdRng::seed_from_u64(1);
+        for g in [-0.6, 0.0, 0.3, 0.9] {
+            let n = 100_000;
+            let mean = (0..n)
+                .map(|_| HenyeyGreenstein::sample_cos_theta(g, rng.gen()))
+                .sum::<f64>()
+                / f64::from(n);
+            assert!((mean - g).abs() < 0.01, "g = {g}, mean = {mean}");
+        }
+    }
+}
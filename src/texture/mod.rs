@@ -3,6 +3,8 @@ mod image;
 mod perlin;
 mod solid_color;
 mod isotropic;
+mod henyey_greenstein;
+mod volume;
 
 use dyn_clonable::clonable;
 use crate::geom::{Color, Point3};
@@ -12,6 +14,8 @@ pub use checkerboard::Checkerboard;
 pub use perlin::Perlin;
 pub use solid_color::SolidColor;
 pub use isotropic::Isotropic;
+pub use henyey_greenstein::{HenyeyGreenstein, Lobe};
+pub use volume::{Noise, Turbulence, Volume, VoxelGrid};
 
 #[clonable]
 pub trait Texture: Clone + Send + Sync {
@@ -62,7 +62,7 @@ impl Perlin {
         Self::interp(corners, u, v, w)
     }
 
-    fn turb(&self, mut p: Point3, depth: usize) -> f64 {
+    pub(super) fn turb(&self, mut p: Point3, depth: usize) -> f64 {
         let mut accum = 0.0;
         let mut weight = 1.0;
 
@@ -75,6 +75,24 @@ impl Perlin {
         accum.abs()
     }
 
+    /// Fractal Brownian motion: several octaves of noise, each at twice the
+    /// frequency and half the weight of the last. The result is roughly
+    /// within `-1..1`.
+    pub(super) fn fbm(&self, mut p: Point3, octaves: usize) -> f64 {
+        let mut accum = 0.0;
+        let mut weight = 1.0;
+        let mut total_weight = 0.0;
+
+        for _ in 0..octaves {
+            accum += weight * self.noise(p);
+            total_weight += weight;
+            weight *= 0.5;
+            p *= 2.0;
+        }
+
+        accum / total_weight
+    }
+
     #[allow(clippy::needless_range_loop)]
     fn interp(corners: [[[Vec3; 2]; 2]; 2], u: f64, v: f64, w: f64) -> f64 {
         let uu = u * u * (3.0 - 2.0 * u);
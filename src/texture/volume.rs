@@ -0,0 +1,181 @@
+use anyhow::{anyhow, Context, Result};
+use dyn_clonable::clonable;
+use std::{fs, path::Path, sync::Arc};
+
+use super::Perlin;
+use crate::{
+    geom::{Aabb, Point3},
+    interpolate::trilinear_interp,
+};
+
+/// A density that varies through space, for filling participating media.
+#[clonable]
+pub trait Volume: Clone + Send + Sync {
+    fn density(&self, p: Point3) -> f64;
+
+    /// An upper bound on `density` anywhere.
+    fn max_density(&self) -> f64;
+}
+
+/// Fractal Brownian motion built from Perlin noise, for smoke and clouds.
+#[derive(Clone)]
+pub struct Noise {
+    perlin: Perlin,
+    /// The density where the noise is at its highest.
+    pub density: f64,
+    /// How many noise features there are per unit of distance.
+    pub frequency: f64,
+    pub octaves: usize,
+    /// The part of the noise's range, from 0 to 1, that is left empty. Higher
+    /// values make sparser, more broken up clouds.
+    pub threshold: f64,
+}
+
+impl Noise {
+    pub fn new(density: f64, frequency: f64, octaves: usize, threshold: f64) -> Self {
+        Self {
+            perlin: Perlin::default(),
+            density,
+            frequency,
+            octaves,
+            threshold,
+        }
+    }
+}
+
+impl Volume for Noise {
+    fn density(&self, p: Point3) -> f64 {
+        let fbm = self.perlin.fbm(self.frequency * p, self.octaves);
+        let coverage =
+            ((0.5 * (1.0 + fbm) - self.threshold) / (1.0 - self.threshold)).clamp(0.0, 1.0);
+        self.density * coverage
+    }
+
+    fn max_density(&self) -> f64 {
+        self.density
+    }
+}
+
+/// Perlin turbulence, whose sharp creases suit flames and glowing gas.
+#[derive(Clone)]
+pub struct Turbulence {
+    perlin: Perlin,
+    /// The density where the turbulence is at its strongest.
+    pub density: f64,
+    /// How many noise features there are per unit of distance.
+    pub frequency: f64,
+    pub octaves: usize,
+}
+
+impl Turbulence {
+    pub fn new(density: f64, frequency: f64, octaves: usize) -> Self {
+        Self {
+            perlin: Perlin::default(),
+            density,
+            frequency,
+            octaves,
+        }
+    }
+}
+
+impl Volume for Turbulence {
+    fn density(&self, p: Point3) -> f64 {
+        self.density * self.perlin.turb(self.frequency * p, self.octaves).min(1.0)
+    }
+
+    fn max_density(&self) -> f64 {
+        self.density
+    }
+}
+
+/// Densities sampled on a regular grid, stretched over a box and
+/// interpolated between the samples.
+#[derive(Clone)]
+pub struct VoxelGrid {
+    bounds: Aabb,
+    resolution: [usize; 3],
+    values: Arc<[f64]>,
+    max_value: f64,
+}
+
+impl VoxelGrid {
+    /// Loads a grid from a text file. The file starts with the number of
+    /// samples along X, Y and Z, followed by all of the samples with X
+    /// changing fastest and Z slowest. Samples are multiplied by `scale`.
+    /// Lines starting with `#` are ignored.
+    pub fn load(path: &Path, bounds: Aabb, scale: f64) -> Result<Self> {
+        let text = fs::read_to_string(path)?;
+        let mut numbers = text
+            .lines()
+            .filter(|line| !line.trim_start().starts_with('#'))
+            .flat_map(str::split_whitespace);
+
+        let mut resolution = [0; 3];
+        for (axis, count) in resolution.iter_mut().enumerate() {
+            *count = numbers
+                .next()
+                .ok_or_else(|| anyhow!("missing grid size"))?
+                .parse()
+                .with_context(|| format!("parsing grid size {}", axis + 1))?;
+            anyhow::ensure!(*count >= 2, "grids need at least 2 samples along each axis");
+        }
+
+        let values: Arc<[f64]> = numbers
+            .map(|n| n.parse::<f64>().map(|v| v * scale))
+            .collect::<Result<_, _>>()
+            .context("parsing grid samples")?;
+        let expected = resolution.iter().product::<usize>();
+        anyhow::ensure!(
+            values.len() == expected,
+            "grid should have {} samples, but has {}",
+            expected,
+            values.len()
+        );
+        anyhow::ensure!(
+            values.iter().all(|v| *v >= 0.0),
+            "grid densities can't be negative"
+        );
+
+        Ok(Self {
+            bounds,
+            resolution,
+            max_value: values.iter().copied().fold(0.0, f64::max),
+            values,
+        })
+    }
+
+    fn value(&self, i: usize, j: usize, k: usize) -> f64 {
+        let [nx, ny, _] = self.resolution;
+        self.values[(k * ny + j) * nx + i]
+    }
+}
+
+impl Volume for VoxelGrid {
+    fn density(&self, p: Point3) -> f64 {
+        let mut cell = [0; 3];
+        let mut fraction = [0.0; 3];
+        for axis in 0..3 {
+            let span = self.bounds.max[axis] - self.bounds.min[axis];
+            let x = (p[axis] - self.bounds.min[axis]) / span * (self.resolution[axis] - 1) as f64;
+            if !(0.0..=(self.resolution[axis] - 1) as f64).contains(&x) {
+                return 0.0;
+            }
+            cell[axis] = (x.floor() as usize).min(self.resolution[axis] - 2);
+            fraction[axis] = x - cell[axis] as f64;
+        }
+
+        let mut corners = [[[0.0; 2]; 2]; 2];
+        for (di, plane) in corners.iter_mut().enumerate() {
+            for (dj, row) in plane.iter_mut().enumerate() {
+                for (dk, corner) in row.iter_mut().enumerate() {
+                    *corner = self.value(cell[0] + di, cell[1] + dj, cell[2] + dk);
+                }
+            }
+        }
+        trilinear_interp(corners, fraction[0], fraction[1], fraction[2])
+    }
+
+    fn max_density(&self) -> f64 {
+        self.max_value
+    }
+}